@@ -1,83 +1,960 @@
 use crate::hdr::*;
+use crate::interval::{self, Recorder, Tick, Timeline};
+use crate::server;
 use crate::task::*;
 use crate::Config;
-use std::collections::HashMap;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use serde::{Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter};
 use std::rc::Rc;
-use std::time::Duration;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+#[derive(clap::ValueEnum, Debug, Clone, Serialize)]
+pub enum Engine {
+    THREAD,
+    TOKIO,
+    #[cfg(feature = "uring")]
+    URING,
+}
+
+impl Engine {
+    /// Whether tasks of the given client type can be driven by this engine.
+    pub fn supports(&self, t: &ClientType) -> bool {
+        match self {
+            Engine::THREAD => {
+                matches!(
+                    t,
+                    ClientType::MEMRS
+                        | ClientType::RSMEM
+                        | ClientType::BASIC
+                        | ClientType::META
+                        | ClientType::REDIS
+                )
+            }
+            Engine::TOKIO => matches!(t, ClientType::TOKIO),
+            // tokio-uring runs a regular tokio runtime underneath
+            #[cfg(feature = "uring")]
+            Engine::URING => matches!(t, ClientType::TOKIO | ClientType::URING),
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Serialize)]
+pub enum Arrival {
+    FIXED,
+    POISSON,
+}
+
+/// Intended send times of one open-loop request stream, each stream taking an
+/// equal share of `--rate`. A pipelined batch takes a single slot.
+struct Schedule {
+    next: Instant,
+    interval: f64,
+    arrival: Arrival,
+    rng: SmallRng,
+}
+
+impl Schedule {
+    /// Returns `None` for closed-loop runs where requests are issued back to back.
+    fn new(c: &Config, streams: i64) -> Option<Self> {
+        let rate = c.rate?;
+        Some(Schedule {
+            next: Instant::now(),
+            interval: (streams * c.pipeline as i64) as f64 / rate,
+            arrival: c.arrival.clone(),
+            rng: SmallRng::from_entropy(),
+        })
+    }
+
+    fn advance(&mut self) -> Instant {
+        let at = self.next;
+        let gap = match self.arrival {
+            Arrival::FIXED => self.interval,
+            // exponential inter-arrival times
+            Arrival::POISSON => -(1.0 - self.rng.gen::<f64>()).ln() * self.interval,
+        };
+        self.next += Duration::from_secs_f64(gap);
+        at
+    }
+
+    /// Blocks until the next slot and returns the time its request is charged
+    /// from for coordinated omission: the intended send time when the stream is
+    /// already behind it, held up by slow responses, otherwise the actual
+    /// wakeup. Oversleeping is the timer's doing rather than the server's, and
+    /// at low rates would alone double the corrected p50. What remains is a
+    /// wakeup late enough to push back the following slot, which that slot is
+    /// charged for. Slots already in the past are not skipped, so a stalled
+    /// stream catches up.
+    fn wait(&mut self) -> Instant {
+        let at = self.advance();
+        let now = Instant::now();
+        if at <= now {
+            return at;
+        }
+        thread::sleep(at - now);
+        Instant::now()
+    }
+
+    async fn wait_async(&mut self) -> Instant {
+        let at = self.advance();
+        if at <= Instant::now() {
+            return at;
+        }
+        tokio::time::sleep_until(at.into()).await;
+        Instant::now()
+    }
+}
+
+/// Wall-clock phases of a run: an unrecorded warm-up followed by either
+/// `--requests` requests or `--duration` of measured load.
+#[derive(Clone, Copy)]
+struct Window {
+    measure_from: Instant,
+    until: Option<Instant>,
+    requests: i64,
+}
+
+impl Window {
+    fn new(c: &Config) -> Self {
+        let measure_from = Instant::now() + c.warmup;
+        Window {
+            measure_from,
+            until: c.duration.map(|d| measure_from + d),
+            requests: c.requests,
+        }
+    }
+
+    fn warming_up(&self) -> bool {
+        Instant::now() < self.measure_from
+    }
+
+    fn done(&self, measured: i64) -> bool {
+        match self.until {
+            Some(until) => Instant::now() >= until,
+            None => measured >= self.requests,
+        }
+    }
+}
+
+/// Failed requests of a run across every thread, checked against `--max-errors`.
+struct Budget {
+    max: Option<u64>,
+    spent: AtomicU64,
+}
+
+impl Budget {
+    fn new(c: &Config) -> Self {
+        Budget {
+            max: c.max_errors,
+            spent: AtomicU64::new(0),
+        }
+    }
+
+    /// Counts a failure, misses excepted.
+    fn spend(&self, class: ErrorClass) {
+        if class != ErrorClass::Miss {
+            self.spent.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn exceeded(&self) -> bool {
+        self.max
+            .is_some_and(|max| self.spent.load(Ordering::Relaxed) > max)
+    }
+}
+
+/// Records a finished request, or its failure, under its op.
+fn record(
+    results: &mut HashMap<String, Result>,
+    budget: &Budget,
+    recorder: &mut Recorder,
+    outcome: TaskOutcome,
+    corrected: impl FnOnce(Duration) -> Option<Duration>,
+    warm: bool,
+) {
+    match outcome {
+        Ok(r) if !warm => {
+            recorder.record(&r.0, Some(r.1));
+            let corrected = corrected(r.1);
+            results.entry(r.0).or_default().record(r.1, r.2, corrected)
+        }
+        Ok(_) => (),
+        Err(e) => {
+            budget.spend(e.1);
+            if !warm {
+                recorder.record(&e.0, None);
+                results.entry(e.0).or_default().record_error(e.1);
+            }
+        }
+    }
+}
+
+/// Where a run's workers wait for each other before timing starts. Unlike a
+/// `Barrier`, a worker that fails to connect, or panics, on its way there
+/// releases the others instead of leaving them waiting forever.
+struct Gate {
+    /// Workers still to arrive, and whether one of them failed
+    state: Mutex<(usize, bool)>,
+    ready: Condvar,
+}
+
+impl Gate {
+    fn new(workers: usize) -> Self {
+        Gate {
+            state: Mutex::new((workers, false)),
+            ready: Condvar::new(),
+        }
+    }
+
+    fn arrive(&self, failed: bool) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.0 -= 1;
+        state.1 |= failed;
+        self.ready.notify_all();
+        let state = self
+            .ready
+            .wait_while(state, |(left, failed)| *left > 0 && !*failed)
+            .unwrap();
+        !state.1
+    }
+}
+
+/// A worker's way to the `Gate`: fails it if dropped before arriving.
+struct Entrant<'a> {
+    gate: &'a Gate,
+    arrived: bool,
+}
+
+impl<'a> Entrant<'a> {
+    fn new(gate: &'a Gate) -> Self {
+        Entrant {
+            gate,
+            arrived: false,
+        }
+    }
+
+    /// Waits for every worker, false if one of them failed and the run is off.
+    fn arrive(mut self) -> bool {
+        self.arrived = true;
+        self.gate.arrive(false)
+    }
+}
+
+impl Drop for Entrant<'_> {
+    fn drop(&mut self) {
+        if !self.arrived {
+            self.gate.arrive(true);
+        }
+    }
+}
 
 pub struct Bench {
-    config: Rc<Config>,
-    times_map: HashMap<String, Vec<Duration>>,
+    config: Arc<Config>,
+    results: HashMap<String, Result>,
+    started: SystemTime,
+    elapsed: Duration,
+    /// The `--interval` time series of the last run, and when its timeline started
+    ticks: Option<(SystemTime, Vec<Tick>)>,
+    /// How the server's statistics moved over the last run, with `--server-stats`
+    server: Option<server::Stats>,
+    /// Where the `--interval` histograms go as each interval is reported
+    log: Option<Arc<Mutex<Log<BufWriter<File>>>>>,
 }
 
 impl Bench {
-    pub fn new(c: Rc<Config>) -> Self {
+    pub fn new(c: Arc<Config>) -> Self {
         Bench {
             config: c,
-            times_map: HashMap::new(),
+            results: HashMap::new(),
+            started: SystemTime::now(),
+            elapsed: Duration::ZERO,
+            ticks: None,
+            server: None,
+            log: None,
         }
     }
 
-    pub fn run(&mut self) {
-        let mut t = task_factory(self.config.clone());
-        t.init();
-        for _ in 0..self.config.requests {
-            let r = t.run();
-            // print!("{r:?},");
-            let t = self.times_map.entry(r.0.clone()).or_default();
-            t.push(r.1);
+    /// Logs the histogram of every `--interval` as it is reported, rather than
+    /// keeping them all in the ticks.
+    pub fn log_intervals(&mut self, log: Arc<Mutex<Log<BufWriter<File>>>>) {
+        self.log = Some(log);
+    }
+
+    /// Fails if a connection cannot be made or the interval log written, or once
+    /// the run exceeded `--max-errors`.
+    pub fn run(&mut self) -> io::Result<()> {
+        // every connection is set up and the keyspace populated before any thread starts timing
+        let gate = Arc::new(Gate::new(self.config.threads as usize));
+        let budget = Arc::new(Budget::new(&self.config));
+        let timeline = self.config.interval.map(|i| Arc::new(Timeline::new(i)));
+        let reporter = timeline.clone().map(|t| {
+            let percentiles = self.config.percentiles.clone();
+            let log = self.log.clone();
+            thread::spawn(move || interval::report(t, percentiles, log))
+        });
+        let (measuring, measured_from) = mpsc::channel();
+        let (started, start) = (SystemTime::now(), Instant::now());
+        let workers: Vec<_> = (0..self.config.threads)
+            .map(|id| {
+                let c = self.config.clone();
+                let gate = gate.clone();
+                let budget = budget.clone();
+                let t = timeline.clone();
+                let init = (id == 0).then(|| measuring.clone());
+                thread::spawn(move || match c.engine {
+                    Engine::THREAD => worker(c, gate, budget, t, init),
+                    Engine::TOKIO => tokio_worker(c, gate, budget, t, init),
+                    #[cfg(feature = "uring")]
+                    Engine::URING => tokio_uring::start(connections(c, gate, budget, t, init)),
+                })
+            })
+            .collect();
+        drop(measuring);
+        // the deltas only cover the measured requests, not populating the
+        // keyspace or the warmup; the first few may land before the snapshot
+        let before = match measured_from.recv() {
+            Ok(from) if self.config.server_stats => {
+                thread::sleep(from.saturating_duration_since(Instant::now()));
+                self.server_snapshot()
+            }
+            _ => None,
+        };
+
+        let mut failed = None;
+        for w in workers {
+            match w.join().expect("worker thread panicked") {
+                Ok(results) => {
+                    for (op, r) in results {
+                        self.results.entry(op).or_default().merge(&r);
+                    }
+                }
+                Err(e) => failed = failed.or(Some(e)),
+            }
+        }
+        if let (Some(t), Some(reporter)) = (timeline, reporter) {
+            t.finish();
+            match reporter.join().expect("reporter thread panicked") {
+                Ok(ticks) => self.ticks = Some(ticks),
+                Err(e) => failed = failed.or(Some(e)),
+            }
+        }
+        if let Some(e) = failed {
+            return Err(e);
+        }
+        if let (Some(before), Some(after)) = (before, self.server_snapshot()) {
+            self.server = Some(server::diff(&self.config, &before, &after));
+        }
+        // only the measured requests count, not connection setup or warmup
+        let (first, last) = self
+            .results
+            .values()
+            .filter_map(|r| r.window)
+            .reduce(|(s, e), (start, end)| (s.min(start), e.max(end)))
+            .unwrap_or((start, Instant::now()));
+        self.started = started + first.duration_since(start);
+        self.elapsed = last - first;
+        if self.results.len() > 1 {
+            let mut all = Result::default();
+            for r in self.results.values() {
+                all.merge(r);
+            }
+            self.results.insert("ALL".to_string(), all);
+        }
+        for r in self.results.values_mut() {
+            r.finish(self.elapsed, &self.config.percentiles);
+        }
+        if budget.exceeded() {
+            return Err(io::Error::other(format!(
+                "aborted after {} failed requests, over --max-errors",
+                budget.spent.load(Ordering::Relaxed)
+            )));
+        }
+        Ok(())
+    }
+
+    /// When the measured requests of the last run started and how long they took.
+    pub fn span(&self) -> (SystemTime, Duration) {
+        (self.started, self.elapsed)
+    }
+
+    /// Takes the `--interval` time series of the last run, with when its first
+    /// interval started.
+    pub fn take_ticks(&mut self) -> Option<(SystemTime, Vec<Tick>)> {
+        self.ticks.take()
+    }
+
+    /// How the server's statistics moved over the last run, if `--server-stats`
+    /// could read them.
+    pub fn server_stats(&self) -> Option<&server::Stats> {
+        self.server.as_ref()
+    }
+
+    /// A run goes ahead without server statistics when they cannot be read.
+    fn server_snapshot(&self) -> Option<server::Snapshot> {
+        if !self.config.server_stats {
+            return None;
         }
+        server::capture(&self.config)
+            .map_err(|e| eprintln!("cannot read server stats: {e}"))
+            .ok()
     }
 
     pub fn result(&self) -> HashMap<&String, Rc<Result>> {
-        self.times_map
+        self.results
             .iter()
-            .map(|(op, times)| {
-                (
-                    op,
-                    Rc::new(Result::new(times, self.config.data_string.len())),
-                )
-            })
+            .map(|(op, r)| (op, Rc::new(r.clone())))
             .collect()
     }
 }
 
-#[derive(Default, Clone)]
+/// Drives `connections` tasks round-robin on the current thread, recording into
+/// per-thread histograms that are merged by `Bench::run`. The keyspace only needs
+/// populating once per run, so only the worker passed `init` does it, then sends
+/// on it when its measured requests start.
+fn worker(
+    c: Arc<Config>,
+    gate: Arc<Gate>,
+    budget: Arc<Budget>,
+    timeline: Option<Arc<Timeline>>,
+    init: Option<mpsc::Sender<Instant>>,
+) -> io::Result<HashMap<String, Result>> {
+    let entrant = Entrant::new(&gate);
+    let mut tasks = (0..c.connections)
+        .map(|_| task_factory(c.clone()))
+        .collect::<io::Result<Vec<_>>>()?;
+    if init.is_some() {
+        tasks[0].init();
+    }
+    if !entrant.arrive() {
+        return Ok(HashMap::new());
+    }
+    if let Some(t) = &timeline {
+        t.begin();
+    }
+
+    // a thread's connections take turns, so they share a single request stream
+    let mut schedule = Schedule::new(&c, c.threads);
+    let window = Window::new(&c);
+    if let Some(init) = init {
+        let _ = init.send(window.measure_from);
+    }
+    let mut recorder = Recorder::new(timeline);
+    let mut results = HashMap::<String, Result>::new();
+    let mut measured = 0;
+    while !window.done(measured) && !budget.exceeded() {
+        let warm = window.warming_up();
+        let mut batch = 1;
+        for t in tasks.iter_mut() {
+            let intended = schedule.as_mut().map(|s| s.wait());
+            let sent = Instant::now();
+            let rs = t.run_batch();
+            batch = rs.len();
+            for r in rs {
+                let corrected = |t| intended.map(|i| sent.duration_since(i) + t);
+                record(&mut results, &budget, &mut recorder, r, corrected, warm);
+            }
+        }
+        if !warm {
+            measured += batch as i64;
+        }
+    }
+    Ok(results)
+}
+
+/// Async counterpart to `worker`: the thread's connections run concurrently on a
+/// single-threaded tokio runtime instead of taking turns.
+fn tokio_worker(
+    c: Arc<Config>,
+    gate: Arc<Gate>,
+    budget: Arc<Budget>,
+    timeline: Option<Arc<Timeline>>,
+    init: Option<mpsc::Sender<Instant>>,
+) -> io::Result<HashMap<String, Result>> {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build tokio runtime");
+    let local = tokio::task::LocalSet::new();
+    local.block_on(&rt, connections(c, gate, budget, timeline, init))
+}
+
+/// Spawns one local task per connection on the current runtime and merges their results.
+async fn connections(
+    c: Arc<Config>,
+    gate: Arc<Gate>,
+    budget: Arc<Budget>,
+    timeline: Option<Arc<Timeline>>,
+    init: Option<mpsc::Sender<Instant>>,
+) -> io::Result<HashMap<String, Result>> {
+    let entrant = Entrant::new(&gate);
+    let mut tasks = Vec::new();
+    for _ in 0..c.connections {
+        tasks.push(async_task_factory(c.clone()).await?);
+    }
+    if init.is_some() {
+        tasks[0].init().await;
+    }
+    if !entrant.arrive() {
+        return Ok(HashMap::new());
+    }
+    if let Some(t) = &timeline {
+        t.begin();
+    }
+
+    let window = Window::new(&c);
+    if let Some(init) = init {
+        let _ = init.send(window.measure_from);
+    }
+    let handles: Vec<_> = tasks
+        .into_iter()
+        .map(|t| {
+            let schedule = Schedule::new(&c, c.threads * c.connections);
+            let recorder = Recorder::new(timeline.clone());
+            tokio::task::spawn_local(drive(t, schedule, window, budget.clone(), recorder))
+        })
+        .collect();
+    let mut results = HashMap::<String, Result>::new();
+    for h in handles {
+        for (op, r) in h.await.expect("connection task panicked") {
+            results.entry(op).or_default().merge(&r);
+        }
+    }
+    Ok(results)
+}
+
+async fn drive(
+    mut t: Box<dyn AsyncTask>,
+    mut schedule: Option<Schedule>,
+    window: Window,
+    budget: Arc<Budget>,
+    mut recorder: Recorder,
+) -> HashMap<String, Result> {
+    let mut results = HashMap::<String, Result>::new();
+    let mut measured = 0;
+    while !window.done(measured) && !budget.exceeded() {
+        let warm = window.warming_up();
+        let intended = match schedule.as_mut() {
+            Some(s) => Some(s.wait_async().await),
+            None => None,
+        };
+        let sent = Instant::now();
+        let r = t.run().await;
+        let corrected = |t| intended.map(|i| sent.duration_since(i) + t);
+        record(&mut results, &budget, &mut recorder, r, corrected, warm);
+        if !warm {
+            measured += 1;
+        }
+    }
+    results
+}
+
+/// Histograms record nanoseconds; JSON output labels every value with its unit.
+#[derive(Default, Clone, Serialize)]
 pub struct Result {
     pub ops: usize,
+    pub bytes: usize,
+    #[serde(rename = "total_us", serialize_with = "micros")]
     pub total: Duration,
+    /// Ops over the wall-clock time of the run's measured requests
+    #[serde(rename = "ops_per_sec")]
+    pub wall_opsps: f64,
+    /// Ops over the summed latency, what one connection would manage back to back
+    #[serde(rename = "latency_ops_per_sec")]
     pub opsps: f64,
+    #[serde(rename = "p50_us", serialize_with = "micros")]
+    pub p50: Duration,
+    #[serde(rename = "p90_us", serialize_with = "micros")]
+    pub p90: Duration,
+    #[serde(rename = "p95_us", serialize_with = "micros")]
+    pub p95: Duration,
+    #[serde(rename = "p99_us", serialize_with = "micros")]
     pub p99: Duration,
+    #[serde(rename = "p99.9_us", serialize_with = "micros")]
+    pub p999: Duration,
+    #[serde(rename = "p99.99_us", serialize_with = "micros")]
+    pub p9999: Duration,
+    #[serde(rename = "min_us", serialize_with = "micros")]
+    pub min: Duration,
+    #[serde(rename = "max_us", serialize_with = "micros")]
+    pub max: Duration,
+    #[serde(rename = "mean_us", serialize_with = "micros")]
+    pub mean: Duration,
+    #[serde(rename = "stddev_us", serialize_with = "micros")]
+    pub stddev: Duration,
+    /// The `--percentiles` to print, with their service and corrected latency
+    #[serde(skip)]
+    pub percentiles: Vec<(f64, Duration, Duration)>,
+    /// Bytes over the wall-clock time of the run, in kilobytes per second
+    #[serde(rename = "kbytes_per_sec")]
     pub kbps: f64,
+    /// The same in gigabits per second
+    #[serde(rename = "gbits_per_sec")]
     pub gbps: f64,
+    /// Service time, measured from when the request was actually sent
     pub histogram: HDR,
+    /// Response time, measured from the intended send time (open-loop runs only)
+    #[serde(skip_serializing_if = "HDR::is_empty")]
+    pub corrected: HDR,
+    #[serde(
+        rename = "corrected_p99_us",
+        serialize_with = "micros",
+        skip_serializing_if = "Duration::is_zero"
+    )]
+    pub corrected_p99: Duration,
+    /// Failed requests by cause; these are not part of the histograms
+    pub errors: BTreeMap<ErrorClass, usize>,
+    /// When the first measured request was sent and the last one completed
+    #[serde(skip)]
+    window: Option<(Instant, Instant)>,
 }
 
 impl Result {
-    fn new(times: &Vec<Duration>, d: usize) -> Self {
-        let mut r = Result::default();
+    fn record(&mut self, t: Duration, bytes: usize, corrected: Option<Duration>) {
+        let end = Instant::now();
+        self.widen((end - t, end));
+        self.ops += 1;
+        self.bytes += bytes;
+        self.total += t;
+        self.histogram += t.as_nanos() as u64;
+        if let Some(corrected) = corrected {
+            self.corrected += corrected.as_nanos() as u64;
+        }
+    }
+
+    fn record_error(&mut self, class: ErrorClass) {
+        *self.errors.entry(class).or_default() += 1;
+    }
 
-        r.ops = times.len();
-        for t in times {
-            r.total += *t;
-            r.histogram += t.as_micros() as u64;
+    fn merge(&mut self, other: &Result) {
+        self.ops += other.ops;
+        self.bytes += other.bytes;
+        self.total += other.total;
+        self.histogram += &other.histogram;
+        self.corrected += &other.corrected;
+        for (class, n) in &other.errors {
+            *self.errors.entry(*class).or_default() += n;
         }
-        r.opsps = r.ops as f64 / r.total.as_secs_f64();
-        r.kbps = d as f64 * r.opsps / 1000 as f64;
-        r.gbps = (d * 8) as f64 * r.opsps / 1000000000 as f64;
-        r.p99 = Duration::from_micros(r.histogram.p99());
+        if let Some(window) = other.window {
+            self.widen(window);
+        }
+    }
 
-        r
+    fn widen(&mut self, (start, end): (Instant, Instant)) {
+        self.window = Some(match self.window {
+            Some((s, e)) => (s.min(start), e.max(end)),
+            None => (start, end),
+        });
+    }
+
+    /// `wall` is the measured time of the whole run, which every op shares, and
+    /// `percentiles` the ones to print.
+    fn finish(&mut self, wall: Duration, percentiles: &[f64]) {
+        if self.ops == 0 {
+            // every request failed
+            return;
+        }
+        self.wall_opsps = self.ops as f64 / wall.as_secs_f64();
+        self.opsps = self.ops as f64 / self.total.as_secs_f64();
+        let bps = self.bytes as f64 / wall.as_secs_f64();
+        self.kbps = bps / 1000.0;
+        self.gbps = bps * 8.0 / 1_000_000_000.0;
+        let h = &self.histogram;
+        let at = |p| Duration::from_nanos(h.percentile(p));
+        self.p50 = at(50.0);
+        self.p90 = at(90.0);
+        self.p95 = at(95.0);
+        self.p99 = Duration::from_nanos(h.p99());
+        self.p999 = at(99.9);
+        self.p9999 = at(99.99);
+        self.min = Duration::from_nanos(h.min());
+        self.max = Duration::from_nanos(h.max());
+        self.mean = Duration::from_secs_f64(h.mean() / 1e9);
+        self.stddev = Duration::from_secs_f64(h.stdev() / 1e9);
+        self.corrected_p99 = Duration::from_nanos(self.corrected.p99());
+        self.percentiles = percentiles
+            .iter()
+            .map(|p| {
+                let corrected = Duration::from_nanos(self.corrected.percentile(*p));
+                (*p, at(*p), corrected)
+            })
+            .collect();
     }
 }
 
+fn micros<S: Serializer>(d: &Duration, s: S) -> std::result::Result<S::Ok, S::Error> {
+    s.serialize_f64(d.as_secs_f64() * 1e6)
+}
+
 impl fmt::Display for Result {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "ops {}; total {:?}; ops/sec {:.2}; p99: {:?}, KBps {:.2}; Gbps {:.2}",
-            self.ops, self.total, self.opsps, self.p99, self.kbps, self.gbps
-        )
+            "ops {}; total {:?}; ops/sec {:.2} (latency-derived {:.2}); KB/s {:.2}; Gbit/s {:.2}",
+            self.ops, self.total, self.wall_opsps, self.opsps, self.kbps, self.gbps
+        )?;
+        let latencies = |corrected: bool| {
+            let ps: Vec<_> = self
+                .percentiles
+                .iter()
+                .map(|(p, t, c)| format!("p{p} {:?}", if corrected { c } else { t }))
+                .collect();
+            ps.join(", ")
+        };
+        write!(
+            f,
+            "\n latency {}; min {:?}, mean {:?}, stddev {:?}, max {:?}",
+            latencies(false),
+            self.min,
+            self.mean,
+            self.stddev,
+            self.max
+        )?;
+        if !self.corrected.is_empty() {
+            write!(f, "\n corrected {}", latencies(true))?;
+        }
+        if !self.errors.is_empty() {
+            let errors: Vec<_> = self
+                .errors
+                .iter()
+                .map(|(class, n)| format!("{} {n}", class.name()))
+                .collect();
+            write!(f, "; errors: {}", errors.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+    use clap::Parser;
+
+    #[test]
+    fn schedule() {
+        let mut s = Schedule {
+            next: Instant::now() + Duration::from_millis(5),
+            interval: 0.005,
+            arrival: Arrival::FIXED,
+            rng: SmallRng::seed_from_u64(0),
+        };
+        // on time: charged from the wakeup, so oversleeping is not a delay
+        let slot = s.next;
+        let charged = s.wait();
+        assert!(charged >= slot && charged <= Instant::now());
+        // behind: charged from the intended time
+        thread::sleep(Duration::from_millis(20));
+        let slot = s.next;
+        assert_eq!(s.wait(), slot);
+        assert_eq!(s.next, slot + Duration::from_millis(5));
+    }
+
+    #[test]
+    fn gate() {
+        let gate = Arc::new(Gate::new(3));
+        let waiting: Vec<_> = (0..2)
+            .map(|_| {
+                let gate = gate.clone();
+                thread::spawn(move || Entrant::new(&gate).arrive())
+            })
+            .collect();
+        // a worker failing on its way releases the others
+        drop(Entrant::new(&gate));
+        for w in waiting {
+            assert!(!w.join().unwrap());
+        }
+        let gate = Gate::new(1);
+        assert!(Entrant::new(&gate).arrive());
+    }
+
+    #[test]
+    fn throughput() {
+        let mut r = Result::default();
+        for ms in [1, 2, 3, 4] {
+            r.record(Duration::from_millis(ms), 250_000, None);
+        }
+        // four requests over a two second window, of 10ms back to back
+        r.finish(Duration::from_secs(2), &[]);
+        assert_eq!(r.wall_opsps, 2.0);
+        assert!((r.opsps - 400.0).abs() < 1e-9, "{}", r.opsps);
+        assert_eq!(r.kbps, 500.0);
+        assert_eq!(r.gbps, 0.004);
+        let json = serde_json::to_value(&r).unwrap();
+        assert_eq!(json["ops_per_sec"], 2.0);
+        assert_eq!(json["kbytes_per_sec"], 500.0);
+        assert_eq!(json["gbits_per_sec"], 0.004);
+        assert_eq!(json["total_us"], 10_000.0);
+
+        // the wall-clock window of merged results spans them all
+        let t = Instant::now();
+        let (mut a, mut b) = (Result::default(), Result::default());
+        a.widen((t, t + Duration::from_secs(1)));
+        b.widen((t + Duration::from_millis(500), t + Duration::from_secs(3)));
+        a.merge(&b);
+        assert_eq!(a.window, Some((t, t + Duration::from_secs(3))));
+
+        // every request failed
+        let mut failed = Result::default();
+        failed.record_error(ErrorClass::Timeout);
+        failed.finish(Duration::from_secs(2), &[]);
+        assert_eq!((failed.wall_opsps, failed.opsps), (0.0, 0.0));
+    }
+
+    #[test]
+    fn percentiles() {
+        let mut r = Result::default();
+        // 1..=1000µs, with the corrected latencies 1ms later
+        for us in 1..=1000 {
+            let t = Duration::from_micros(us);
+            r.record(t, 0, Some(t + Duration::from_millis(1)));
+        }
+        r.finish(Duration::from_secs(1), &[50.0, 99.9]);
+        // within the histogram's three significant digits
+        let close = |d: Duration, us: f64| (d.as_secs_f64() * 1e6 - us).abs() <= us / 1000.0;
+        for (d, us) in [
+            (r.p50, 500.0),
+            (r.p90, 900.0),
+            (r.p95, 950.0),
+            (r.p99, 990.0),
+            // 99.9% of 1000 comes out a hair over 999 in floating point
+            (r.p999, 1000.0),
+            (r.p9999, 1000.0),
+            (r.min, 1.0),
+            (r.max, 1000.0),
+            (r.mean, 500.5),
+            (r.stddev, 288.675),
+            (r.corrected_p99, 1990.0),
+        ] {
+            assert!(close(d, us), "{d:?} is not {us}µs");
+        }
+        assert_eq!(r.percentiles.len(), 2);
+        let (p, t, corrected) = r.percentiles[1];
+        assert_eq!((p, t), (99.9, r.p999));
+        assert!(close(corrected, 2000.0), "{corrected:?}");
+
+        let json = serde_json::to_value(&r).unwrap();
+        for field in [
+            "p50_us",
+            "p90_us",
+            "p95_us",
+            "p99_us",
+            "p99.9_us",
+            "p99.99_us",
+            "min_us",
+            "max_us",
+            "mean_us",
+            "stddev_us",
+            "corrected_p99_us",
+        ] {
+            assert!(json[field].is_f64(), "{field}");
+        }
+        assert_eq!(json["p99.9_us"], r.p999.as_secs_f64() * 1e6);
+        let shown = r.to_string();
+        assert!(shown.contains(&format!("latency p50 {:?}, p99.9 {:?};", r.p50, r.p999)));
+        assert!(shown.contains(&format!("corrected p50 {:?}", r.percentiles[0].2)));
+    }
+
+    /// The config of a run against a fresh mock server.
+    fn config(args: &[&str]) -> Arc<Config> {
+        let addr = mock::Server::new().listen_tcp("127.0.0.1:0").unwrap();
+        let port = addr.port().to_string();
+        let mut argv = vec!["bench", "-p", &port, "-K", "20"];
+        argv.extend(args);
+        crate::prepare(Config::try_parse_from(argv).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn budget() {
+        let c = config(&["--max-errors", "1"]);
+        let budget = Budget::new(&c);
+        budget.spend(ErrorClass::Miss);
+        budget.spend(ErrorClass::Timeout);
+        assert!(!budget.exceeded());
+        budget.spend(ErrorClass::Miss);
+        budget.spend(ErrorClass::Reset);
+        assert!(budget.exceeded());
+        assert_eq!(budget.spent.load(Ordering::Relaxed), 2);
+
+        let unlimited = Budget::new(&config(&[]));
+        for _ in 0..100 {
+            unlimited.spend(ErrorClass::Protocol);
+        }
+        assert!(!unlimited.exceeded());
+    }
+
+    #[test]
+    fn misses() {
+        // deleting keys deleted already misses, which never aborts a run
+        let c = config(&[
+            "-t",
+            "basic",
+            "--ops",
+            "delete",
+            "-n",
+            "200",
+            "--max-errors",
+            "0",
+        ]);
+        let mut b = Bench::new(c);
+        b.run().unwrap();
+        let r = &b.result()[&"DELETE".to_string()];
+        assert!(r.errors[&ErrorClass::Miss] >= 180, "{:?}", r.errors);
+        assert_eq!(r.errors.len(), 1);
+        assert_eq!(r.ops + r.errors[&ErrorClass::Miss], 200);
+    }
+
+    #[test]
+    fn max_errors() {
+        // the mock speaks memcached, so every REDIS request fails
+        let c = config(&[
+            "-t",
+            "redis",
+            "-d",
+            "0",
+            "--ops",
+            "get",
+            "-T",
+            "2",
+            "-n",
+            "1000",
+            "--max-errors",
+            "10",
+        ]);
+        let mut b = Bench::new(c);
+        let e = b.run().unwrap_err();
+        assert!(e.to_string().starts_with("aborted after"), "{e}");
+        let results = b.result();
+        let r = &results[&"GET".to_string()];
+        assert_eq!(r.ops, 0);
+        let failed: usize = r.errors.values().sum();
+        assert_eq!(r.errors.keys().collect::<Vec<_>>(), [&ErrorClass::Protocol]);
+        // each worker stops at its next request once the budget is spent
+        assert!(failed > 10 && failed < 100, "{failed}");
+    }
+
+    #[test]
+    fn unreachable() {
+        // nothing listens on a port just given back
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+            .to_string();
+        for args in [&["-t", "basic"][..], &["-t", "tokio", "-e", "tokio"]] {
+            let mut argv = vec!["bench", "-p", &port, "-T", "4", "-C", "2"];
+            argv.extend(args);
+            let c = crate::prepare(Config::try_parse_from(argv).unwrap()).unwrap();
+            let (done, finished) = mpsc::channel();
+            thread::spawn(move || done.send(Bench::new(c).run().is_err()));
+            assert_eq!(finished.recv_timeout(Duration::from_secs(10)), Ok(true));
+        }
     }
 }
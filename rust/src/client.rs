@@ -1,13 +1,42 @@
+use std::io::ErrorKind;
 use std::io::{self, Read, Write};
 use std::net::TcpStream;
 use std::os::unix::net::UnixStream;
-use std::{str, io::ErrorKind};
+use std::time::Duration;
+use std::{error, fmt, str};
 
-enum Stream {
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+pub(crate) enum Stream {
     Unix(UnixStream),
     Tcp(TcpStream),
 }
 
+impl Stream {
+    /// Connects over UDS when `addr` looks like a socket path, TCP otherwise.
+    pub(crate) fn connect(addr: &str) -> io::Result<Self> {
+        if addr.contains(".sock") {
+            Ok(Stream::Unix(UnixStream::connect(addr)?))
+        } else {
+            Ok(Stream::Tcp(TcpStream::connect(addr)?))
+        }
+    }
+
+    /// Applies `timeout` to every read and write, `None` blocks indefinitely.
+    pub(crate) fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Unix(s) => {
+                s.set_read_timeout(timeout)?;
+                s.set_write_timeout(timeout)
+            }
+            Stream::Tcp(s) => {
+                s.set_read_timeout(timeout)?;
+                s.set_write_timeout(timeout)
+            }
+        }
+    }
+}
+
 impl Write for Stream {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         match self {
@@ -33,18 +62,92 @@ impl Read for Stream {
     }
 }
 
+/// A value returned by a retrieval command.
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct Item {
+    pub key: String,
+    pub flags: u32,
+    /// Only set by `gets`
+    pub cas: Option<u64>,
+    pub value: Vec<u8>,
+}
+
+/// Outcome of a storage command.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Store {
+    Stored,
+    /// The condition of an `add`, `replace`, `append` or `prepend` was not met
+    NotStored,
+    /// The item was modified since it was fetched by `gets`
+    Exists,
+    /// The item of a `cas` does not exist
+    NotFound,
+}
+
+/// An error reply from the server, carried inside the returned `io::Error`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerError {
+    /// `ERROR`: the server did not recognise the command
+    Unknown,
+    /// `CLIENT_ERROR <msg>`: the request was malformed
+    Client(String),
+    /// `SERVER_ERROR <msg>`: the server failed to serve a valid request, eg. out of memory
+    Server(String),
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerError::Unknown => write!(f, "ERROR"),
+            ServerError::Client(msg) => write!(f, "CLIENT_ERROR {msg}"),
+            ServerError::Server(msg) => write!(f, "SERVER_ERROR {msg}"),
+        }
+    }
+}
+
+impl error::Error for ServerError {}
+
+/// Maps a response line the caller did not expect to an error: a `ServerError`
+/// for error replies, `InvalidData` for anything else.
+pub(crate) fn unexpected(line: &str) -> io::Error {
+    let e = if line == "ERROR" {
+        ServerError::Unknown
+    } else if let Some(msg) = line.strip_prefix("CLIENT_ERROR ") {
+        ServerError::Client(msg.to_string())
+    } else if let Some(msg) = line.strip_prefix("SERVER_ERROR ") {
+        ServerError::Server(msg.to_string())
+    } else {
+        return io::Error::new(
+            ErrorKind::InvalidData,
+            format!("unexpected response: {line}"),
+        );
+    };
+    io::Error::other(e)
+}
+
+/// Text protocol client. Requests are encoded into a write buffer and sent in a
+/// single write; responses are parsed out of a read buffer that may already hold
+/// the start of the next one, which is what lets callers pipeline with the
+/// `push_*`, `send` and `read_*` methods.
 pub struct Client {
     stream: Stream,
+    wbuf: Vec<u8>,
+    rbuf: ReadBuf,
 }
 
+#[allow(dead_code)]
 impl Client {
     pub fn connect(addr: &str) -> io::Result<Self> {
-        let stream = if addr.contains(".sock") {
-            Stream::Unix(UnixStream::connect(addr)?)
-        } else {
-            Stream::Tcp(TcpStream::connect(addr)?)
-        };
-        Ok(Self { stream })
+        Ok(Self {
+            stream: Stream::connect(addr)?,
+            wbuf: Vec::new(),
+            rbuf: ReadBuf::default(),
+        })
+    }
+
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_timeout(timeout)
     }
 
     pub fn set(
@@ -53,51 +156,495 @@ impl Client {
         value: &[u8],
         flags: u32,
         expiration: u32,
-    ) -> Result<bool, std::io::Error> {
-        let cmd = format!("set {} {} {} {}\r\n", key, flags, expiration, value.len());
-        self.stream.write_all(cmd.as_bytes())?;
-        self.stream.write_all(value)?;
-        self.stream.write_all(b"\r\n")?;
-        self.stream.flush()?;
-
-        let mut buf = [0; 128];
-        let n = self.stream.read(&mut buf)?;
-        let response = std::str::from_utf8(&buf[..n]).unwrap();
-
-        if response == "STORED\r\n" {
-            Ok(true)
-        } else {
-            Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("memcached error: {}", response),
-            ))
-        }
+    ) -> io::Result<Store> {
+        self.push_store("set", key, value, flags, expiration, None);
+        self.send()?;
+        self.read_stored()
+    }
+
+    /// Returns `Store::NotStored` if the key already exists.
+    pub fn add(
+        &mut self,
+        key: &str,
+        value: &[u8],
+        flags: u32,
+        expiration: u32,
+    ) -> io::Result<Store> {
+        self.push_store("add", key, value, flags, expiration, None);
+        self.send()?;
+        self.read_stored()
+    }
+
+    /// Returns `Store::NotStored` if the key does not exist.
+    pub fn replace(
+        &mut self,
+        key: &str,
+        value: &[u8],
+        flags: u32,
+        expiration: u32,
+    ) -> io::Result<Store> {
+        self.push_store("replace", key, value, flags, expiration, None);
+        self.send()?;
+        self.read_stored()
+    }
+
+    /// Returns `Store::NotStored` if the key does not exist.
+    pub fn append(&mut self, key: &str, value: &[u8]) -> io::Result<Store> {
+        self.push_store("append", key, value, 0, 0, None);
+        self.send()?;
+        self.read_stored()
+    }
+
+    /// Returns `Store::NotStored` if the key does not exist.
+    pub fn prepend(&mut self, key: &str, value: &[u8]) -> io::Result<Store> {
+        self.push_store("prepend", key, value, 0, 0, None);
+        self.send()?;
+        self.read_stored()
+    }
+
+    /// Returns `Store::Exists` if the item was modified since `gets`, or
+    /// `Store::NotFound` if it does not exist.
+    pub fn cas(
+        &mut self,
+        key: &str,
+        value: &[u8],
+        flags: u32,
+        expiration: u32,
+        cas: u64,
+    ) -> io::Result<Store> {
+        self.push_store("cas", key, value, flags, expiration, Some(cas));
+        self.send()?;
+        self.read_stored()
     }
 
     pub fn get(&mut self, key: &str) -> io::Result<Option<Vec<u8>>> {
-        write!(self.stream, "get {key}\r\n")?;
-        self.stream.flush()?;
+        self.push_line(&format!("get {key}"));
+        self.send()?;
+        Ok(self.read_items()?.into_iter().next().map(|i| i.value))
+    }
+
+    /// Like `get`, also returning the CAS unique of the item.
+    pub fn gets(&mut self, key: &str) -> io::Result<Option<(Vec<u8>, u64)>> {
+        self.push_line(&format!("gets {key}"));
+        self.send()?;
+        Ok(self
+            .read_items()?
+            .into_iter()
+            .next()
+            .map(|i| (i.value, i.cas.unwrap_or_default())))
+    }
+
+    /// Fetches several keys in one round trip; misses are left out of the result.
+    pub fn get_multi(&mut self, keys: &[&str]) -> io::Result<Vec<Item>> {
+        self.push_line(&format!("get {}", keys.join(" ")));
+        self.send()?;
+        self.read_items()
+    }
+
+    /// Get and touch: fetches the key and updates its expiration.
+    pub fn gat(&mut self, expiration: u32, key: &str) -> io::Result<Option<Vec<u8>>> {
+        self.push_line(&format!("gat {expiration} {key}"));
+        self.send()?;
+        Ok(self.read_items()?.into_iter().next().map(|i| i.value))
+    }
 
-        let mut response = Vec::new();
-        let mut buf = [0u8; 4096];
+    /// Returns `Ok(false)` if the key does not exist.
+    pub fn delete(&mut self, key: &str) -> io::Result<bool> {
+        self.push_line(&format!("delete {key}"));
+        self.send()?;
+        self.read_found()
+    }
+
+    /// Returns the new value, or `None` if the key does not exist.
+    pub fn incr(&mut self, key: &str, delta: u64) -> io::Result<Option<u64>> {
+        self.push_line(&format!("incr {key} {delta}"));
+        self.send()?;
+        self.read_number()
+    }
+
+    /// Returns the new value, or `None` if the key does not exist.
+    pub fn decr(&mut self, key: &str, delta: u64) -> io::Result<Option<u64>> {
+        self.push_line(&format!("decr {key} {delta}"));
+        self.send()?;
+        self.read_number()
+    }
+
+    /// Returns `Ok(false)` if the key does not exist.
+    pub fn touch(&mut self, key: &str, expiration: u32) -> io::Result<bool> {
+        self.push_line(&format!("touch {key} {expiration}"));
+        self.send()?;
+        self.read_found()
+    }
+
+    /// Returns the `STAT` lines of `stats`, or `stats <group>` such as `slabs`,
+    /// in the order the server sent them.
+    pub fn stats(&mut self, group: Option<&str>) -> io::Result<Vec<(String, String)>> {
+        match group {
+            Some(g) => self.push_line(&format!("stats {g}")),
+            None => self.push_line("stats"),
+        }
+        self.send()?;
+        let mut stats = Vec::new();
         loop {
-            let n = self.stream.read(&mut buf)?;
-            response.extend_from_slice(&buf[..n]);
-            if response.ends_with(b"\r\nEND\r\n") {
-                break;
+            let line = self.rbuf.read_line(&mut self.stream)?;
+            if line == "END" {
+                return Ok(stats);
+            }
+            match line.strip_prefix("STAT ").and_then(|s| s.split_once(' ')) {
+                Some((name, value)) => stats.push((name.to_string(), value.to_string())),
+                None => return Err(unexpected(line)),
             }
         }
+    }
 
-        let response_lines: Vec<&[u8]> = response.split(|b| *b == b'\r' || *b == b'\n').collect();
-        if response_lines.len() < 3 {
-            return Err(ErrorKind::InvalidData.into());
+    /// Buffers a storage command (`set`, `add`, `replace`, `append`, `prepend`,
+    /// or `cas` when `cas` is given); read its response with `read_stored`.
+    pub fn push_store(
+        &mut self,
+        cmd: &str,
+        key: &str,
+        value: &[u8],
+        flags: u32,
+        expiration: u32,
+        cas: Option<u64>,
+    ) {
+        write!(
+            self.wbuf,
+            "{} {} {} {} {}",
+            cmd,
+            key,
+            flags,
+            expiration,
+            value.len()
+        )
+        .unwrap();
+        if let Some(cas) = cas {
+            write!(self.wbuf, " {cas}").unwrap();
+        }
+        self.wbuf.extend_from_slice(b"\r\n");
+        self.wbuf.extend_from_slice(value);
+        self.wbuf.extend_from_slice(b"\r\n");
+    }
+
+    /// Buffers a single line command, without its terminator.
+    pub fn push_line(&mut self, cmd: &str) {
+        self.wbuf.extend_from_slice(cmd.as_bytes());
+        self.wbuf.extend_from_slice(b"\r\n");
+    }
+
+    /// Writes every buffered command at once.
+    pub fn send(&mut self) -> io::Result<()> {
+        let res = self
+            .stream
+            .write_all(&self.wbuf)
+            .and_then(|_| self.stream.flush());
+        self.wbuf.clear();
+        res
+    }
+
+    /// Reads the response to a storage command.
+    pub fn read_stored(&mut self) -> io::Result<Store> {
+        match self.rbuf.read_line(&mut self.stream)? {
+            "STORED" => Ok(Store::Stored),
+            "NOT_STORED" => Ok(Store::NotStored),
+            "EXISTS" => Ok(Store::Exists),
+            "NOT_FOUND" => Ok(Store::NotFound),
+            r => Err(unexpected(r)),
+        }
+    }
+
+    /// Reads a `delete` or `touch` response; `Ok(false)` for NOT_FOUND.
+    pub fn read_found(&mut self) -> io::Result<bool> {
+        match self.rbuf.read_line(&mut self.stream)? {
+            "DELETED" | "TOUCHED" => Ok(true),
+            "NOT_FOUND" => Ok(false),
+            r => Err(unexpected(r)),
+        }
+    }
+
+    /// Reads an `incr`/`decr` response; `None` for NOT_FOUND.
+    pub fn read_number(&mut self) -> io::Result<Option<u64>> {
+        match self.rbuf.read_line(&mut self.stream)? {
+            "NOT_FOUND" => Ok(None),
+            r => r.parse().map(Some).map_err(|_| unexpected(r)),
+        }
+    }
+
+    /// Reads the `VALUE` blocks of a retrieval response up to `END`.
+    pub fn read_items(&mut self) -> io::Result<Vec<Item>> {
+        let mut items = Vec::new();
+        loop {
+            let header = self.rbuf.read_line(&mut self.stream)?;
+            if header == "END" {
+                return Ok(items);
+            }
+            let fields: Vec<_> = header.split(' ').collect();
+            let (key, flags, len, cas) = match fields[..] {
+                ["VALUE", key, flags, len] => (key, flags, len, None),
+                ["VALUE", key, flags, len, cas] => (key, flags, len, Some(cas)),
+                _ => return Err(unexpected(header)),
+            };
+            let mut item = Item {
+                key: key.to_string(),
+                flags: flags.parse().map_err(|_| unexpected(header))?,
+                cas: cas
+                    .map(|c| c.parse())
+                    .transpose()
+                    .map_err(|_| unexpected(header))?,
+                value: Vec::new(),
+            };
+            // the value may contain CR/LF, so take its length from the VALUE header
+            let len: usize = len.parse().map_err(|_| unexpected(header))?;
+            item.value = self.rbuf.read_value(&mut self.stream, len)?;
+            items.push(item);
         }
+    }
+}
+
+/// Bytes requested from the socket by each read.
+const READ_SIZE: usize = 4096;
 
-        let header = std::str::from_utf8(response_lines[0]).unwrap();
-        if header == "END" {
+/// Read side of the text protocol clients. Replies are parsed out of a buffer
+/// that may hold a partial reply or the start of the next one, so they can be
+/// split across reads anywhere, even inside a `\r\n`, and only complete ones
+/// are consumed.
+#[derive(Default)]
+pub(crate) struct ReadBuf {
+    buf: Vec<u8>,
+    pos: usize,
+    /// How many bytes after `pos` are known not to start a line terminator
+    scanned: usize,
+}
+
+impl ReadBuf {
+    /// Returns the next line without its terminator, reading from `r` until it
+    /// is complete.
+    pub(crate) fn read_line<R: Read>(&mut self, r: &mut R) -> io::Result<&str> {
+        let (start, end) = loop {
+            if let Some(line) = self.find_line() {
+                break line;
+            }
+            self.fill(r)?;
+        };
+        self.text(start, end)
+    }
+
+    /// Returns the next `size` bytes, reading from `r` until they and the
+    /// `\r\n` ending them are complete.
+    pub(crate) fn read_value<R: Read>(&mut self, r: &mut R, size: usize) -> io::Result<Vec<u8>> {
+        loop {
+            if let Some(value) = self.value(size)? {
+                return Ok(value);
+            }
+            self.fill(r)?;
+        }
+    }
+
+    /// `read_line` over an async reader.
+    pub(crate) async fn read_line_async<R: AsyncRead + Unpin>(
+        &mut self,
+        r: &mut R,
+    ) -> io::Result<&str> {
+        let (start, end) = loop {
+            if let Some(line) = self.find_line() {
+                break line;
+            }
+            let len = self.reserve();
+            let read = r.read(&mut self.buf[len..]).await;
+            self.filled(len, read)?;
+        };
+        self.text(start, end)
+    }
+
+    /// `read_value` over an async reader.
+    pub(crate) async fn read_value_async<R: AsyncRead + Unpin>(
+        &mut self,
+        r: &mut R,
+        size: usize,
+    ) -> io::Result<Vec<u8>> {
+        loop {
+            if let Some(value) = self.value(size)? {
+                return Ok(value);
+            }
+            let len = self.reserve();
+            let read = r.read(&mut self.buf[len..]).await;
+            self.filled(len, read)?;
+        }
+    }
+
+    /// Like `read_line`, without reading: `None` until the line is complete.
+    #[cfg(feature = "uring")]
+    pub(crate) fn line(&mut self) -> io::Result<Option<&str>> {
+        match self.find_line() {
+            Some((start, end)) => self.text(start, end).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Like `read_value`, without reading: `None` until the data is complete.
+    pub(crate) fn value(&mut self, size: usize) -> io::Result<Option<Vec<u8>>> {
+        let end = self.pos + size;
+        if self.buf.len() < end + 2 {
+            return Ok(None);
+        }
+        if &self.buf[end..end + 2] != b"\r\n" {
             return Err(ErrorKind::InvalidData.into());
         }
+        let value = self.buf[self.pos..end].to_vec();
+        self.consume(end + 2);
+        Ok(Some(value))
+    }
+
+    /// Reads once from `r`, dropping what was consumed first.
+    fn fill<R: Read>(&mut self, r: &mut R) -> io::Result<()> {
+        let len = self.reserve();
+        let read = r.read(&mut self.buf[len..]);
+        self.filled(len, read)
+    }
+
+    /// Lends the buffer to a read that takes ownership of it, like an io_uring
+    /// submission, with consumed data dropped and room reserved past its end;
+    /// return it with `give_back`.
+    #[cfg(feature = "uring")]
+    pub(crate) fn lend(&mut self) -> Vec<u8> {
+        self.buf.drain(..self.pos);
+        self.pos = 0;
+        self.buf.reserve(READ_SIZE);
+        std::mem::take(&mut self.buf)
+    }
+
+    /// Takes back the buffer of `lend` after `read` appended to it.
+    #[cfg(feature = "uring")]
+    pub(crate) fn give_back(&mut self, buf: Vec<u8>, read: io::Result<usize>) -> io::Result<()> {
+        self.buf = buf;
+        if read? == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        Ok(())
+    }
+
+    /// Finds the next complete line, returning its bounds without the
+    /// terminator and consuming it.
+    fn find_line(&mut self) -> Option<(usize, usize)> {
+        let pending = &self.buf[self.pos..];
+        // resume the search where the last partial read left off
+        match pending[self.scanned..]
+            .windows(2)
+            .position(|w| w == b"\r\n")
+        {
+            Some(i) => {
+                let (start, end) = (self.pos, self.pos + self.scanned + i);
+                self.consume(end + 2);
+                Some((start, end))
+            }
+            None => {
+                self.scanned = pending.len().saturating_sub(1);
+                None
+            }
+        }
+    }
+
+    fn text(&self, start: usize, end: usize) -> io::Result<&str> {
+        str::from_utf8(&self.buf[start..end]).map_err(|_| ErrorKind::InvalidData.into())
+    }
+
+    fn consume(&mut self, pos: usize) {
+        self.pos = pos;
+        self.scanned = 0;
+    }
+
+    /// Drops consumed data and makes room for a read, returning where it goes.
+    fn reserve(&mut self) -> usize {
+        self.buf.drain(..self.pos);
+        self.pos = 0;
+        let len = self.buf.len();
+        self.buf.resize(len + READ_SIZE, 0);
+        len
+    }
+
+    /// Keeps what a read into the room made by `reserve` returned.
+    fn filled(&mut self, len: usize, read: io::Result<usize>) -> io::Result<()> {
+        let n = *read.as_ref().unwrap_or(&0);
+        self.buf.truncate(len + n);
+        if read? == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns `data` split at `splits`, one piece per read.
+    struct Chunks(Vec<Vec<u8>>);
+
+    impl Chunks {
+        fn new(data: &[u8], splits: &[usize]) -> Self {
+            let mut bounds = vec![0];
+            bounds.extend_from_slice(splits);
+            bounds.push(data.len());
+            Chunks(
+                bounds
+                    .windows(2)
+                    .map(|w| data[w[0]..w[1]].to_vec())
+                    .collect(),
+            )
+        }
+    }
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            let chunk = self.0.remove(0);
+            buf[..chunk.len()].copy_from_slice(&chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    const REPLY: &[u8] = b"VALUE k 0 10\r\nEND\r\nEND\r\n\r\nEND\r\nSTORED\r\n";
+
+    /// Parses `REPLY`, a `get` whose value ends like a response, and a `set`.
+    fn parse(r: &mut Chunks) {
+        let mut rbuf = ReadBuf::default();
+        assert_eq!(rbuf.read_line(r).unwrap(), "VALUE k 0 10");
+        assert_eq!(rbuf.read_value(r, 10).unwrap(), b"END\r\nEND\r\n");
+        assert_eq!(rbuf.read_line(r).unwrap(), "END");
+        assert_eq!(rbuf.read_line(r).unwrap(), "STORED");
+        let e = rbuf.read_line(r).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn single_read() {
+        parse(&mut Chunks::new(REPLY, &[]));
+    }
+
+    #[test]
+    fn split_reads() {
+        // every split point, including between the `\r` and `\n` of each line
+        for i in 1..REPLY.len() {
+            parse(&mut Chunks::new(REPLY, &[i]));
+        }
+        let bytes: Vec<_> = (1..REPLY.len()).collect();
+        parse(&mut Chunks::new(REPLY, &bytes));
+    }
+
+    #[test]
+    fn split_terminator() {
+        let mut r = Chunks::new(b"STORED\r\nEND\r\n", &[7, 12]);
+        let mut rbuf = ReadBuf::default();
+        assert_eq!(rbuf.read_line(&mut r).unwrap(), "STORED");
+        assert_eq!(rbuf.read_line(&mut r).unwrap(), "END");
+    }
 
-        Ok(Some(response_lines[2].to_vec()))
+    #[test]
+    fn unterminated_value() {
+        let mut r = Chunks::new(b"abcd\r\n", &[2]);
+        let e = ReadBuf::default().read_value(&mut r, 3).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
     }
 }
@@ -0,0 +1,240 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
+use serde::Deserialize;
+
+use crate::hdr::HDR;
+use crate::stats;
+
+/// Compare two `--json` result files op by op
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Results to compare against
+    baseline: PathBuf,
+    /// Results of the change being evaluated
+    candidate: PathBuf,
+    /// Significance level of the tests
+    #[arg(long, default_value_t = 0.05)]
+    alpha: f64,
+}
+
+/// The parts of a `json::Report` the comparison needs.
+#[derive(Deserialize)]
+struct Saved {
+    runs: Vec<BTreeMap<String, SavedResult>>,
+}
+
+#[derive(Deserialize)]
+struct SavedResult {
+    ops: usize,
+    ops_per_sec: f64,
+    histogram: SavedHistogram,
+}
+
+#[derive(Deserialize)]
+struct SavedHistogram {
+    encoded: String,
+}
+
+/// An op's per-run throughput and its latencies merged over every run.
+struct Samples {
+    ops_per_sec: Vec<f64>,
+    histogram: HDR,
+}
+
+fn load(path: &Path) -> io::Result<BTreeMap<String, Samples>> {
+    let saved: Saved = serde_json::from_reader(BufReader::new(File::open(path)?))
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("{}: {e}", path.display())))?;
+    let mut ops = BTreeMap::<String, Samples>::new();
+    for run in saved.runs {
+        for (op, r) in run.into_iter().filter(|(_, r)| r.ops > 0) {
+            let s = ops.entry(op).or_insert_with(|| Samples {
+                ops_per_sec: Vec::new(),
+                histogram: HDR::new(),
+            });
+            s.ops_per_sec.push(r.ops_per_sec);
+            s.histogram += &HDR::decode(&r.histogram.encoded)?;
+        }
+    }
+    Ok(ops)
+}
+
+fn change(from: f64, to: f64) -> String {
+    if from == 0.0 {
+        return "n/a".to_string();
+    }
+    format!("{:+.2}%", (to - from) / from * 100.0)
+}
+
+fn verdict(significant: bool) -> &'static str {
+    if significant {
+        "significant"
+    } else {
+        "not significant"
+    }
+}
+
+/// Throughput is compared across runs with the Mann-Whitney U test, so it needs
+/// several runs per side; `percentiles` by bootstrapping the merged histograms.
+pub fn run(args: &Args, percentiles: &[f64]) -> io::Result<()> {
+    report(args, percentiles, &mut io::stdout().lock())
+}
+
+fn report<W: Write>(args: &Args, percentiles: &[f64], out: &mut W) -> io::Result<()> {
+    let (baseline, candidate) = (load(&args.baseline)?, load(&args.candidate)?);
+    // a fixed seed keeps repeated comparisons of the same files identical
+    let mut rng = SmallRng::seed_from_u64(0);
+    writeln!(out, "BASELINE: {}", args.baseline.display())?;
+    writeln!(out, "CANDIDATE: {}", args.candidate.display())?;
+    for (op, a) in &baseline {
+        let Some(b) = candidate.get(op) else {
+            writeln!(out, "\nOP: {op} \n only in baseline")?;
+            continue;
+        };
+        writeln!(out, "\nOP: {op} ")?;
+        let mean = |v: &[f64]| v.iter().sum::<f64>() / v.len() as f64;
+        let (from, to) = (mean(&a.ops_per_sec), mean(&b.ops_per_sec));
+        let p = stats::mann_whitney(&a.ops_per_sec, &b.ops_per_sec);
+        writeln!(
+            out,
+            " ops/sec: {from:.2} -> {to:.2} ({}); p {p:.4} over {} vs {} runs, {}",
+            change(from, to),
+            a.ops_per_sec.len(),
+            b.ops_per_sec.len(),
+            verdict(p < args.alpha)
+        )?;
+        let cis = stats::bootstrap(
+            &a.histogram,
+            &b.histogram,
+            percentiles,
+            args.alpha,
+            &mut rng,
+        );
+        for (p, (lo, hi)) in percentiles.iter().zip(cis) {
+            let (from, to) = (
+                a.histogram.percentile(*p) as f64 / 1000.0,
+                b.histogram.percentile(*p) as f64 / 1000.0,
+            );
+            let (lo, hi) = (lo / 1000.0, hi / 1000.0);
+            writeln!(
+                out,
+                " p{p} µs: {from:.2} -> {to:.2} ({}); {}% ci of delta [{lo:.2}, {hi:.2}], {}",
+                change(from, to),
+                (1.0 - args.alpha) * 100.0,
+                verdict(lo > 0.0 || hi < 0.0)
+            )?;
+        }
+    }
+    for op in candidate.keys().filter(|op| !baseline.contains_key(*op)) {
+        writeln!(out, "\nOP: {op} \n only in candidate")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// Writes a results file with one GET result per run.
+    fn results(name: &str, runs: &[(usize, f64, u64)]) -> PathBuf {
+        let runs: Vec<_> = runs
+            .iter()
+            .map(|(ops, ops_per_sec, latency)| {
+                let mut h = HDR::new();
+                for _ in 0..*ops {
+                    h += *latency;
+                }
+                json!({"GET": {
+                    "ops": ops,
+                    "ops_per_sec": ops_per_sec,
+                    "histogram": {"encoded": h.encode()},
+                }})
+            })
+            .collect();
+        let path =
+            std::env::temp_dir().join(format!("bench-compare-{}-{name}", std::process::id()));
+        std::fs::write(&path, json!({ "runs": runs }).to_string()).unwrap();
+        path
+    }
+
+    #[test]
+    fn load_runs() {
+        let path = results("load", &[(10, 100.0, 1000), (0, 0.0, 0), (30, 300.0, 2000)]);
+        let ops = load(&path).unwrap();
+        // runs without a successful op are left out
+        assert_eq!(ops["GET"].ops_per_sec, [100.0, 300.0]);
+        assert_eq!(ops["GET"].histogram.recorded(), [(1000, 10), (2000, 30)]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn report_round_trip() {
+        use crate::bench::Result;
+        use clap::Parser;
+
+        let c = crate::Config::try_parse_from(["bench"]).unwrap();
+        let runs: Vec<BTreeMap<_, _>> = [(150.0, 1000), (250.0, 2000)]
+            .into_iter()
+            .map(|(ops_per_sec, latency)| {
+                let mut r = Result::default();
+                r.ops = 3;
+                r.wall_opsps = ops_per_sec;
+                for _ in 0..r.ops {
+                    r.histogram += latency;
+                }
+                BTreeMap::from([
+                    ("GET".to_string(), r),
+                    ("SET".to_string(), Result::default()),
+                ])
+            })
+            .collect();
+        let summary = stats::OpSummary::of(&runs, &c.percentiles);
+        let path =
+            std::env::temp_dir().join(format!("bench-compare-{}-report", std::process::id()));
+        crate::json::write(&path, &c, &runs, &[], &summary).unwrap();
+
+        let ops = load(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(ops.keys().collect::<Vec<_>>(), ["GET"]);
+        assert_eq!(ops["GET"].ops_per_sec, [150.0, 250.0]);
+        assert_eq!(ops["GET"].histogram.recorded(), [(1000, 3), (2000, 3)]);
+    }
+
+    #[test]
+    fn changes() {
+        assert_eq!(change(200.0, 250.0), "+25.00%");
+        assert_eq!(change(200.0, 150.0), "-25.00%");
+        assert_eq!(change(0.0, 1.0), "n/a");
+    }
+
+    #[test]
+    fn compare() {
+        let args = Args {
+            baseline: results("a", &[(10, 100.0, 1000), (10, 101.0, 1000)]),
+            candidate: results("b", &[(10, 120.0, 900), (10, 121.0, 900)]),
+            alpha: 0.05,
+        };
+        let mut out = Vec::new();
+        report(&args, &[50.0], &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = out.lines().skip(2).collect();
+        assert_eq!(
+            lines,
+            [
+                "",
+                "OP: GET ",
+                // the exact p of two runs each is at least 2 / C(4, 2)
+                " ops/sec: 100.50 -> 120.50 (+19.90%); p 0.3333 over 2 vs 2 runs, not significant",
+                " p50 µs: 1.00 -> 0.90 (-10.00%); 95% ci of delta [-0.10, -0.10], significant",
+            ]
+        );
+        std::fs::remove_file(args.baseline).unwrap();
+        std::fs::remove_file(args.candidate).unwrap();
+    }
+}
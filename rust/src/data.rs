@@ -0,0 +1,347 @@
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+
+use rand::distributions::{Distribution, Uniform, WeightedIndex};
+use rand::rngs::SmallRng;
+use rand::{Rng, RngCore, SeedableRng};
+use rand_distr::Normal;
+use serde::Serialize;
+
+#[derive(clap::ValueEnum, Debug, Clone, Serialize)]
+pub enum Payload {
+    /// Every byte is `x`
+    FILL,
+    /// Random bytes, compressible according to `--compressibility`
+    RANDOM,
+    /// Values start at a random file in `--corpus` and run on into the next
+    /// ones if it is too short
+    CORPUS,
+}
+
+/// Size of the blocks `RANDOM` payloads are built from; the compressible tail of
+/// each block repeats a single byte.
+const BLOCK: usize = 64;
+
+/// The buffer every written value is cut from.
+#[derive(Debug, Default)]
+pub struct Values {
+    bytes: Vec<u8>,
+    /// Where each corpus sample starts, empty for the other payloads
+    samples: Vec<usize>,
+}
+
+impl Values {
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Where a value of `size` bytes is in the buffer: a prefix, or for CORPUS
+    /// payloads a random sample, running on into the samples after it if it is
+    /// too short.
+    fn pick(&self, size: usize, rng: &mut impl Rng) -> Range<usize> {
+        let start = match self.samples.len() {
+            0 => 0,
+            n => self.samples[rng.gen_range(0..n)],
+        };
+        start..start + size
+    }
+
+    pub fn get(&self, at: Range<usize>) -> &[u8] {
+        &self.bytes[at]
+    }
+}
+
+/// Builds the payload buffer for values of up to `len` bytes.
+pub fn payload(
+    kind: &Payload,
+    len: usize,
+    compressibility: f64,
+    corpus: Option<&Path>,
+) -> io::Result<Values> {
+    let prefixes = |bytes| Values {
+        bytes,
+        samples: Vec::new(),
+    };
+    match kind {
+        Payload::FILL => Ok(prefixes(vec![b'x'; len])),
+        Payload::RANDOM => {
+            let mut rng = SmallRng::from_entropy();
+            let random = BLOCK - (compressibility.clamp(0.0, 1.0) * BLOCK as f64) as usize;
+            let mut buf = vec![b'x'; len];
+            for block in buf.chunks_mut(BLOCK) {
+                let n = random.min(block.len());
+                rng.fill_bytes(&mut block[..n]);
+            }
+            Ok(prefixes(buf))
+        }
+        Payload::CORPUS => {
+            let dir = corpus.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "--corpus is required")
+            })?;
+            let mut paths = fs::read_dir(dir)?
+                .map(|e| e.map(|e| e.path()))
+                .collect::<io::Result<Vec<_>>>()?;
+            paths.sort();
+            let (mut bytes, mut samples) = (Vec::new(), Vec::new());
+            for path in paths.iter().filter(|p| p.is_file()) {
+                let sample = fs::read(path)?;
+                if !sample.is_empty() {
+                    samples.push(bytes.len());
+                    bytes.extend(sample);
+                }
+            }
+            if len == 0 {
+                return Ok(Values::default());
+            }
+            if bytes.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("corpus {} is empty", dir.display()),
+                ));
+            }
+            // the corpus repeats, so a value from the last sample has room to run on
+            let total = bytes.len() + len;
+            let bytes = bytes.into_iter().cycle().take(total).collect();
+            Ok(Values { bytes, samples })
+        }
+    }
+}
+
+/// Payload size spec accepted by `--data`:
+/// `N`, `MIN-MAX` (uniform), `normal:MEAN:STDDEV` or `@FILE` with one
+/// `SIZE WEIGHT` bucket per line.
+#[derive(Debug, Clone, Serialize)]
+pub enum DataSize {
+    Fixed(usize),
+    Uniform(usize, usize),
+    Normal(f64, f64),
+    Buckets(Vec<(usize, f64)>),
+}
+
+impl DataSize {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let size = |v: &str| {
+            v.trim()
+                .parse::<usize>()
+                .map_err(|_| format!("invalid data size: {v}"))
+        };
+        let float = |v: &str| {
+            v.trim()
+                .parse::<f64>()
+                .map_err(|_| format!("invalid number: {v}"))
+        };
+
+        if let Some(path) = s.strip_prefix('@') {
+            let contents =
+                fs::read_to_string(path).map_err(|e| format!("failed to read {path}: {e}"))?;
+            let mut buckets = Vec::new();
+            for line in contents.lines().map(str::trim) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                match line.split_whitespace().collect::<Vec<_>>()[..] {
+                    [s, w] => buckets.push((size(s)?, float(w)?)),
+                    _ => return Err(format!("invalid bucket line: {line}")),
+                }
+            }
+            if buckets.is_empty() {
+                return Err(format!("no buckets in {path}"));
+            }
+            Ok(DataSize::Buckets(buckets))
+        } else if let Some(params) = s.strip_prefix("normal:") {
+            match params.split(':').collect::<Vec<_>>()[..] {
+                [mean, stddev] => Ok(DataSize::Normal(float(mean)?, float(stddev)?)),
+                _ => Err(format!("invalid normal distribution: {s}")),
+            }
+        } else if let Some((min, max)) = s.split_once('-') {
+            let (min, max) = (size(min)?, size(max)?);
+            if min > max {
+                return Err(format!("invalid data range: {s}"));
+            }
+            Ok(DataSize::Uniform(min, max))
+        } else {
+            Ok(DataSize::Fixed(size(s)?))
+        }
+    }
+
+    /// Largest size that can be generated, used to size the payload buffer.
+    pub fn max(&self) -> usize {
+        match self {
+            DataSize::Fixed(n) => *n,
+            DataSize::Uniform(_, max) => *max,
+            DataSize::Normal(mean, stddev) => (mean + 6.0 * stddev).max(0.0) as usize,
+            DataSize::Buckets(b) => b.iter().map(|(s, _)| *s).max().unwrap_or(0),
+        }
+    }
+}
+
+enum Sizes {
+    Fixed(usize),
+    Uniform(Uniform<usize>),
+    Normal(Normal<f64>, usize),
+    Buckets(Vec<usize>, WeightedIndex<f64>),
+}
+
+/// Picks a payload size for every write according to a `DataSize`.
+pub struct SizeGen {
+    sizes: Sizes,
+    rng: SmallRng,
+}
+
+impl SizeGen {
+    pub fn new(d: &DataSize) -> Result<Self, String> {
+        let sizes = match d {
+            DataSize::Fixed(n) => Sizes::Fixed(*n),
+            DataSize::Uniform(min, max) => Sizes::Uniform(Uniform::new_inclusive(min, max)),
+            DataSize::Normal(mean, stddev) => Sizes::Normal(
+                Normal::new(*mean, *stddev).map_err(|e| format!("invalid data stddev: {e}"))?,
+                d.max(),
+            ),
+            DataSize::Buckets(b) => Sizes::Buckets(
+                b.iter().map(|(s, _)| *s).collect(),
+                WeightedIndex::new(b.iter().map(|(_, w)| *w))
+                    .map_err(|e| format!("invalid bucket weights: {e}"))?,
+            ),
+        };
+        Ok(SizeGen {
+            sizes,
+            rng: SmallRng::from_entropy(),
+        })
+    }
+
+    /// Picks the next value out of `values`.
+    pub fn value<'a>(&mut self, values: &'a Values) -> &'a [u8] {
+        values.get(self.place(values))
+    }
+
+    /// Picks where the next value is in `values`, to take it later.
+    pub fn place(&mut self, values: &Values) -> Range<usize> {
+        let size = self.next();
+        values.pick(size, &mut self.rng)
+    }
+
+    fn next(&mut self) -> usize {
+        match &self.sizes {
+            Sizes::Fixed(n) => *n,
+            Sizes::Uniform(u) => u.sample(&mut self.rng),
+            Sizes::Normal(n, max) => (n.sample(&mut self.rng).round().max(0.0) as usize).min(*max),
+            Sizes::Buckets(sizes, w) => sizes[w.sample(&mut self.rng)],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("bench-data-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn parse() {
+        assert!(matches!(DataSize::parse("100"), Ok(DataSize::Fixed(100))));
+        assert!(matches!(
+            DataSize::parse("10-20"),
+            Ok(DataSize::Uniform(10, 20))
+        ));
+        assert!(matches!(
+            DataSize::parse("normal:100:10.5"),
+            Ok(DataSize::Normal(m, s)) if m == 100.0 && s == 10.5
+        ));
+        let buckets = dir("buckets");
+        fs::write(&buckets, "# size weight\n100 0.9\n\n10000 0.1\n").unwrap();
+        let d = DataSize::parse(&format!("@{}", buckets.display())).unwrap();
+        assert!(matches!(&d, DataSize::Buckets(b) if b[..] == [(100, 0.9), (10000, 0.1)]));
+        assert_eq!(d.max(), 10000);
+
+        for invalid in [
+            "",
+            "-1",
+            "ten",
+            "20-10",
+            "10-",
+            "normal:100",
+            "normal:a:1",
+            "@/nonexistent",
+        ] {
+            assert!(DataSize::parse(invalid).is_err(), "{invalid}");
+        }
+        fs::write(&buckets, "# nothing\n").unwrap();
+        assert!(DataSize::parse(&format!("@{}", buckets.display())).is_err());
+        fs::write(&buckets, "100 0.5 1\n").unwrap();
+        assert!(DataSize::parse(&format!("@{}", buckets.display())).is_err());
+        fs::remove_file(&buckets).unwrap();
+    }
+
+    #[test]
+    fn sizes() {
+        let sizes = |d: &DataSize| {
+            let mut g = SizeGen::new(d).unwrap();
+            (0..10_000).map(|_| g.next()).collect::<Vec<_>>()
+        };
+        assert!(sizes(&DataSize::Fixed(7)).iter().all(|s| *s == 7));
+        let uniform = sizes(&DataSize::Uniform(10, 20));
+        assert!(uniform.iter().all(|s| (10..=20).contains(s)));
+        assert!(uniform.contains(&10) && uniform.contains(&20));
+        // clamped to zero and to the payload buffer
+        let normal = DataSize::Normal(10.0, 100.0);
+        assert!(sizes(&normal).iter().all(|s| *s <= normal.max()));
+        assert!(sizes(&normal).contains(&0));
+        let buckets = sizes(&DataSize::Buckets(vec![(1, 0.9), (1000, 0.1)]));
+        assert!(buckets.iter().all(|s| *s == 1 || *s == 1000));
+        let large = buckets.iter().filter(|s| **s == 1000).count();
+        assert!((800..1200).contains(&large), "{large}");
+
+        assert!(SizeGen::new(&DataSize::Normal(10.0, f64::NAN)).is_err());
+        assert!(SizeGen::new(&DataSize::Buckets(vec![(1, 0.0)])).is_err());
+    }
+
+    #[test]
+    fn payloads() {
+        let fill = payload(&Payload::FILL, 100, 0.0, None).unwrap();
+        let mut g = SizeGen::new(&DataSize::Uniform(1, 100)).unwrap();
+        for _ in 0..100 {
+            let v = g.value(&fill);
+            assert!(!v.is_empty() && v.len() <= 100 && v.iter().all(|b| *b == b'x'));
+        }
+
+        // the compressible tail of every block is left as is
+        let random = payload(&Payload::RANDOM, 1000, 0.75, None).unwrap();
+        assert_eq!(random.bytes.len(), 1000);
+        for block in random.bytes.chunks(BLOCK) {
+            assert!(block[BLOCK / 4..].iter().all(|b| *b == b'x'));
+            assert!(block[..BLOCK / 4].iter().any(|b| *b != b'x'));
+        }
+
+        assert!(payload(&Payload::CORPUS, 10, 0.0, None).is_err());
+        assert!(payload(&Payload::FILL, 0, 0.0, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn corpus() {
+        let corpus = dir("corpus");
+        fs::create_dir_all(&corpus).unwrap();
+        for (name, sample) in [("a", "aaaa"), ("b", ""), ("c", "cccccccc")] {
+            fs::write(corpus.join(name), sample).unwrap();
+        }
+        let values = payload(&Payload::CORPUS, 6, 0.0, Some(&corpus)).unwrap();
+        assert_eq!(values.samples, [0, 4]);
+        assert_eq!(values.bytes, b"aaaaccccccccaaaacc");
+
+        // every value starts at a sample, the short one running on into the next
+        let mut g = SizeGen::new(&DataSize::Fixed(6)).unwrap();
+        let picked: Vec<_> = (0..100).map(|_| g.value(&values)).collect();
+        assert!(picked.iter().all(|v| *v == b"aaaacc" || *v == b"cccccc"));
+        assert!(picked.contains(&&b"aaaacc"[..]) && picked.contains(&&b"cccccc"[..]));
+
+        for name in ["a", "b", "c"] {
+            fs::write(corpus.join(name), "").unwrap();
+        }
+        let e = payload(&Payload::CORPUS, 6, 0.0, Some(&corpus)).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        fs::remove_dir_all(corpus).unwrap();
+    }
+}
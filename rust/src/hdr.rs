@@ -1,22 +1,110 @@
+use std::collections::BTreeMap;
 use std::fmt::Display;
 use std::io;
-use std::io::Write;
+use std::io::{ErrorKind, Write};
 use std::ops::AddAssign;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
 
+use hdrhistogram::serialization::interval_log::{
+    IntervalLogIterator, IntervalLogWriterBuilder, LogEntry, Tag,
+};
+use hdrhistogram::serialization::{Deserializer, Serializer as _, V2DeflateSerializer};
 use hdrhistogram::Histogram;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
 
+/// Percentiles reported alongside the encoded histogram in JSON output and
+/// compared across runs.
+pub const PERCENTILES: [f64; 6] = [50.0, 90.0, 95.0, 99.0, 99.9, 99.99];
+
+/// Lowest discernible and highest trackable value in nanoseconds, and significant
+/// digits, of every histogram. Set once from the config by `configure`.
+static BOUNDS: OnceLock<(u64, u64, u8)> = OnceLock::new();
+const DEFAULT_BOUNDS: (u64, u64, u8) = (1, 60_000_000_000, 3);
+
+/// Sets the bounds of every histogram created afterwards; only the first call counts.
+pub fn configure(low: Duration, high: Duration, digits: u8) -> io::Result<()> {
+    let bounds = (low.as_nanos().max(1) as u64, high.as_nanos() as u64, digits);
+    Histogram::<u64>::new_with_bounds(bounds.0, bounds.1, bounds.2).map_err(|e| {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid histogram bounds: {e:?}"),
+        )
+    })?;
+    let _ = BOUNDS.set(bounds);
+    Ok(())
+}
+
+/// Latencies in nanoseconds. Values above the configured range are clamped to it.
 #[derive(Debug, Clone)]
 pub struct HDR(hdrhistogram::Histogram<u64>);
 
 impl HDR {
     pub fn new() -> Self {
-        HDR(Histogram::<u64>::new_with_bounds(10, 60000000, 3).unwrap())
+        let (low, high, digits) = *BOUNDS.get().unwrap_or(&DEFAULT_BOUNDS);
+        HDR(Histogram::<u64>::new_with_bounds(low, high, digits).unwrap())
     }
 
     pub fn p99(&self) -> u64 {
         self.0.value_at_quantile(0.99)
     }
 
+    pub fn percentile(&self, p: f64) -> u64 {
+        self.0.value_at_percentile(p)
+    }
+
+    pub fn min(&self) -> u64 {
+        self.0.min()
+    }
+
+    pub fn max(&self) -> u64 {
+        self.0.max()
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.0.mean()
+    }
+
+    pub fn stdev(&self) -> f64 {
+        self.0.stdev()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The histogram in the compressed HdrHistogram V2 format, base64 encoded
+    /// as in the log format, so other HdrHistogram implementations can decode it.
+    pub fn encode(&self) -> String {
+        let mut buf = Vec::new();
+        V2DeflateSerializer::new()
+            .serialize(&self.0, &mut buf)
+            .expect("failed to serialize histogram");
+        base64::encode(buf)
+    }
+
+    /// Reverses `encode`.
+    pub fn decode(encoded: &str) -> io::Result<Self> {
+        let invalid = |e: String| io::Error::new(ErrorKind::InvalidData, e);
+        let encoded = base64::decode(encoded)
+            .map_err(|e| invalid(format!("invalid histogram encoding: {e}")))?;
+        let h: Histogram<u64> = Deserializer::new()
+            .deserialize(&mut encoded.as_slice())
+            .map_err(|e| invalid(format!("invalid histogram: {e:?}")))?;
+        let mut hdr = HDR::new();
+        hdr += &HDR(h);
+        Ok(hdr)
+    }
+
+    /// Each distinct recorded value with its count, in ascending order.
+    pub fn recorded(&self) -> Vec<(u64, u64)> {
+        self.0
+            .iter_recorded()
+            .map(|v| (v.value_iterated_to(), v.count_at_value()))
+            .collect()
+    }
+
+    /// Writes the percentile distribution in the `.hgrm` text format, in microseconds.
     pub fn percentiles<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
         let quantile_precision = 6;
         let ticks_per_half = 10;
@@ -25,7 +113,7 @@ impl HDR {
         writer.write_all(
             format!(
                 "{:>12} {:>quantile_precision$} {:>10} {:>14}\n\n",
-                "Value",
+                "Value(µs)",
                 "Percentile",
                 "TotalCount",
                 "1/(1-Percentile)",
@@ -105,9 +193,130 @@ impl HDR {
     }
 }
 
+/// One histogram of an HdrHistogram interval log.
+pub struct Interval {
+    pub tag: Option<String>,
+    /// Offset from the log's StartTime
+    pub start: Duration,
+    pub duration: Duration,
+    pub histogram: HDR,
+}
+
+/// An HdrHistogram V2 compressed interval log, the format read by
+/// HistogramLogAnalyzer and the other HdrHistogram implementations, written one
+/// interval at a time so the histograms need not be kept until the end. Values
+/// are in nanoseconds, so the max column is scaled to milliseconds.
+pub struct Log<W: Write> {
+    writer: W,
+    start: SystemTime,
+}
+
+impl<W: Write> Log<W> {
+    /// Starts a log whose interval offsets count from `start`.
+    pub fn new(mut writer: W, start: SystemTime) -> io::Result<Self> {
+        IntervalLogWriterBuilder::new()
+            .add_comment("Logged with bench, values in nanoseconds")
+            .with_start_time(start)
+            .with_base_time(start)
+            .with_max_value_divisor(MAX_DIVISOR)
+            .begin_log_with(&mut writer, &mut V2DeflateSerializer::new())?;
+        Ok(Log { writer, start })
+    }
+
+    pub fn start(&self) -> SystemTime {
+        self.start
+    }
+
+    /// Appends an interval as `IntervalLogWriter::write_histogram` would.
+    pub fn write(&mut self, i: &Interval) -> io::Result<()> {
+        if let Some(t) = &i.tag {
+            Tag::new(t).ok_or_else(|| {
+                io::Error::new(ErrorKind::InvalidInput, format!("invalid log tag: {t}"))
+            })?;
+            write!(self.writer, "Tag={t},")?;
+        }
+        writeln!(
+            self.writer,
+            "{:.3},{:.3},{:.3},{}",
+            i.start.as_secs_f64(),
+            i.duration.as_secs_f64(),
+            i.histogram.max() as f64 / MAX_DIVISOR,
+            i.histogram.encode()
+        )
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+const MAX_DIVISOR: f64 = 1_000_000.0;
+
+/// Interval timestamps from this far past the epoch on are absolute.
+const ABSOLUTE: Duration = Duration::from_secs(365 * 24 * 3600);
+
+/// Reads back every interval of a log written by `Log`, or any other
+/// HdrHistogram V2 interval log. Start offsets are relative to the BaseTime, or
+/// the StartTime if the log has no BaseTime.
+pub fn read_log(data: &[u8]) -> io::Result<Vec<Interval>> {
+    let invalid = |e: String| io::Error::new(ErrorKind::InvalidData, e);
+    let mut base = None;
+    let mut intervals = Vec::new();
+    for entry in IntervalLogIterator::new(data) {
+        match entry.map_err(|e| invalid(format!("invalid histogram log: {e:?}")))? {
+            LogEntry::BaseTime(t) => base = Some(t),
+            LogEntry::StartTime(t) => base = base.or(Some(t)),
+            LogEntry::Interval(i) => intervals.push(Interval {
+                tag: i.tag().map(|t| t.as_str().to_string()),
+                start: match i.start_timestamp() {
+                    // like the Java reader, small timestamps are already offsets
+                    t if t < ABSOLUTE => t,
+                    t => t.saturating_sub(base.unwrap_or_default()),
+                },
+                duration: i.duration(),
+                histogram: HDR::decode(i.encoded_histogram())?,
+            }),
+        }
+    }
+    Ok(intervals)
+}
+
+impl Serialize for HDR {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        // in microseconds like the rest of the report; the encoded histogram
+        // keeps the recorded nanoseconds
+        let us = |ns: f64| ns / 1000.0;
+        let percentiles: BTreeMap<_, _> = PERCENTILES
+            .iter()
+            .map(|p| (format!("p{p}"), us(self.percentile(*p) as f64)))
+            .collect();
+        let mut h = s.serialize_struct("HDR", 7)?;
+        h.serialize_field("count", &self.0.len())?;
+        h.serialize_field("min_us", &us(self.0.min() as f64))?;
+        h.serialize_field("max_us", &us(self.0.max() as f64))?;
+        h.serialize_field("mean_us", &us(self.0.mean()))?;
+        h.serialize_field("stdev_us", &us(self.0.stdev()))?;
+        h.serialize_field("percentiles_us", &percentiles)?;
+        h.serialize_field("encoded", &self.encode())?;
+        h.end()
+    }
+}
+
 impl AddAssign<u64> for HDR {
     fn add_assign(&mut self, value: u64) {
-        self.0 += value;
+        self.0.saturating_record(value);
+    }
+}
+
+impl AddAssign<&HDR> for HDR {
+    fn add_assign(&mut self, other: &HDR) {
+        if self.0.add(&other.0).is_err() {
+            // recorded with wider bounds, eg. read from a file, so clamp its values
+            for v in other.0.iter_recorded() {
+                self.0
+                    .saturating_record_n(v.value_iterated_to(), v.count_at_value());
+            }
+        }
     }
 }
 
@@ -116,3 +325,101 @@ impl Default for HDR {
         HDR::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram(values: &[u64]) -> HDR {
+        let mut h = HDR::new();
+        for v in values {
+            h += *v;
+        }
+        h
+    }
+
+    #[test]
+    fn log_round_trip() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let intervals = [
+            Interval {
+                tag: Some("GET".to_string()),
+                start: Duration::ZERO,
+                duration: Duration::from_millis(1500),
+                histogram: histogram(&[1_000, 2_000, 50_000_000]),
+            },
+            Interval {
+                tag: None,
+                start: Duration::from_millis(1500),
+                duration: Duration::from_millis(250),
+                histogram: histogram(&[700; 42]),
+            },
+        ];
+        let mut log = Log::new(Vec::new(), start).unwrap();
+        for i in &intervals {
+            log.write(i).unwrap();
+        }
+
+        let read = read_log(&log.writer).unwrap();
+        assert_eq!(read.len(), intervals.len());
+        for (r, w) in read.iter().zip(&intervals) {
+            assert_eq!(r.tag, w.tag);
+            assert_eq!((r.start, r.duration), (w.start, w.duration));
+            assert_eq!(r.histogram.0.len(), w.histogram.0.len());
+            assert_eq!(r.histogram.recorded(), w.histogram.recorded());
+            assert_eq!(
+                (r.histogram.min(), r.histogram.max()),
+                (w.histogram.min(), w.histogram.max())
+            );
+        }
+    }
+
+    #[test]
+    fn absolute_timestamps() {
+        let log = format!(
+            "#[StartTime: 1700000000.000 (seconds since epoch)]\n1700000002.500,1.000,0.001,{}\n",
+            histogram(&[700]).encode()
+        );
+        let read = read_log(log.as_bytes()).unwrap();
+        assert_eq!(read[0].start, Duration::from_millis(2500));
+        assert_eq!(read[0].histogram.recorded(), [(700, 1)]);
+    }
+
+    #[test]
+    fn json() {
+        let h = histogram(&[1_000, 2_000, 1_000, 2_000]);
+        let json = serde_json::to_value(&h).unwrap();
+        let keys: Vec<_> = json.as_object().unwrap().keys().cloned().collect();
+        assert_eq!(
+            keys,
+            [
+                "count",
+                "min_us",
+                "max_us",
+                "mean_us",
+                "stdev_us",
+                "percentiles_us",
+                "encoded"
+            ]
+        );
+        assert_eq!(json["count"], 4);
+        assert_eq!(
+            (&json["min_us"], &json["max_us"]),
+            (&1.0.into(), &2.0.into())
+        );
+        assert_eq!(
+            (&json["mean_us"], &json["stdev_us"]),
+            (&1.5.into(), &0.5.into())
+        );
+        assert_eq!(json["percentiles_us"]["p50"], 1.0);
+        assert_eq!(json["percentiles_us"]["p99.99"], 2.0);
+        let encoded = json["encoded"].as_str().unwrap();
+        assert_eq!(HDR::decode(encoded).unwrap().recorded(), h.recorded());
+    }
+
+    #[test]
+    fn invalid_log() {
+        let e = read_log(b"0.000,1.000,1.0,notbase64\n").err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,493 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::Serialize;
+
+use crate::hdr::{self, HDR};
+
+/// One op's requests that completed within an interval.
+#[derive(Default, Clone)]
+pub struct Slice {
+    pub ops: usize,
+    pub errors: usize,
+    pub histogram: HDR,
+}
+
+impl Slice {
+    fn merge(&mut self, other: &Slice) {
+        self.ops += other.ops;
+        self.errors += other.errors;
+        self.histogram += &other.histogram;
+    }
+}
+
+/// One op's requests in a reported interval, its histogram reduced to what the
+/// time series shows.
+#[derive(Debug, PartialEq)]
+pub struct Summary {
+    pub ops: usize,
+    pub errors: usize,
+    /// Latency in nanoseconds at each of the reported percentiles, in order
+    pub latencies: Vec<u64>,
+    pub max: u64,
+}
+
+impl Summary {
+    fn of(s: &Slice, percentiles: &[f64]) -> Self {
+        Summary {
+            ops: s.ops,
+            errors: s.errors,
+            latencies: percentiles
+                .iter()
+                .map(|p| s.histogram.percentile(*p))
+                .collect(),
+            max: s.histogram.max(),
+        }
+    }
+}
+
+/// A reported interval of a run.
+pub struct Tick {
+    /// Offset from the start of the timeline
+    pub start: Duration,
+    pub duration: Duration,
+    pub ops: BTreeMap<String, Summary>,
+}
+
+/// Every worker's slices of a run cut into `--interval` periods, shared by the
+/// workers and the reporter.
+pub struct Timeline {
+    period: Duration,
+    /// Set when the workers start, after connection setup
+    start: OnceLock<(Instant, SystemTime)>,
+    slices: Mutex<BTreeMap<u32, HashMap<String, Slice>>>,
+    done: AtomicBool,
+}
+
+impl Timeline {
+    pub fn new(period: Duration) -> Self {
+        Timeline {
+            period,
+            start: OnceLock::new(),
+            slices: Mutex::new(BTreeMap::new()),
+            done: AtomicBool::new(false),
+        }
+    }
+
+    /// Starts the timeline; only the first call counts.
+    pub fn begin(&self) {
+        self.start
+            .get_or_init(|| (Instant::now(), SystemTime::now()));
+    }
+
+    /// Stops the reporter once the workers are done.
+    pub fn finish(&self) {
+        self.begin();
+        self.done.store(true, Ordering::Release);
+    }
+
+    fn index(&self, at: Instant) -> u32 {
+        let start = self.start.get().map_or(at, |s| s.0);
+        (at.saturating_duration_since(start).as_nanos() / self.period.as_nanos()) as u32
+    }
+
+    fn hand_over(&self, index: u32, slices: &mut HashMap<String, Slice>) {
+        let mut all = self.slices.lock().unwrap();
+        let interval = all.entry(index).or_default();
+        for (op, s) in slices.drain() {
+            interval.entry(op).or_default().merge(&s);
+        }
+    }
+
+    fn take(&self, until: Option<u32>) -> Vec<(u32, HashMap<String, Slice>)> {
+        let mut all = self.slices.lock().unwrap();
+        let keep = match until {
+            Some(until) => all.split_off(&until),
+            None => BTreeMap::new(),
+        };
+        std::mem::replace(&mut *all, keep).into_iter().collect()
+    }
+}
+
+/// A worker's slices of the current interval, handed to the timeline when the
+/// interval ends or the worker finishes. Does nothing without `--interval`.
+pub struct Recorder {
+    timeline: Option<Arc<Timeline>>,
+    index: u32,
+    slices: HashMap<String, Slice>,
+}
+
+impl Recorder {
+    pub fn new(timeline: Option<Arc<Timeline>>) -> Self {
+        Recorder {
+            timeline,
+            index: 0,
+            slices: HashMap::new(),
+        }
+    }
+
+    /// Records a completed request, `None` for a failed one.
+    pub fn record(&mut self, op: &str, latency: Option<Duration>) {
+        let Some(timeline) = &self.timeline else {
+            return;
+        };
+        let index = timeline.index(Instant::now());
+        if index != self.index {
+            timeline.hand_over(self.index, &mut self.slices);
+            self.index = index;
+        }
+        let s = match self.slices.get_mut(op) {
+            Some(s) => s,
+            None => self.slices.entry(op.to_string()).or_default(),
+        };
+        match latency {
+            Some(t) => {
+                s.ops += 1;
+                s.histogram += t.as_nanos() as u64;
+            }
+            None => s.errors += 1,
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Some(timeline) = &self.timeline {
+            timeline.hand_over(self.index, &mut self.slices);
+        }
+    }
+}
+
+/// Prints each interval of `timeline` as it ends until the run finishes, logging
+/// its histograms to `log` if given, and returns them all with the time the
+/// timeline started. An interval is reported one period late to give blocked
+/// workers time to hand it over; anything handed over later counts in the next
+/// interval reported. Only the summaries of reported intervals are kept, so a
+/// long run with a short period does not hold on to a histogram per interval.
+/// Fails if the log could not be written.
+pub fn report<W: Write>(
+    timeline: Arc<Timeline>,
+    percentiles: Vec<f64>,
+    log: Option<Arc<Mutex<hdr::Log<W>>>>,
+) -> io::Result<(SystemTime, Vec<Tick>)> {
+    let period = timeline.period;
+    let (start, started) = loop {
+        if let Some(s) = timeline.start.get() {
+            break *s;
+        }
+        thread::sleep(Duration::from_millis(1));
+    };
+    // the log may have started before this run's timeline
+    let offset = log.as_ref().map_or(Duration::ZERO, |log| {
+        let log_start = log.lock().unwrap().start();
+        started.duration_since(log_start).unwrap_or_default()
+    });
+    let mut logged = Ok(());
+    let mut pending = BTreeMap::<u32, BTreeMap<String, Slice>>::new();
+    let mut ticks = Vec::new();
+    let mut reported = |index: u32, duration: Duration, ops: BTreeMap<String, Slice>| {
+        let tick = Tick {
+            start: period * index,
+            duration,
+            ops: ops
+                .iter()
+                .map(|(op, s)| (op.clone(), Summary::of(s, &percentiles)))
+                .collect(),
+        };
+        print_tick(&tick, &percentiles);
+        if let (Some(log), Ok(())) = (&log, &logged) {
+            let mut log = log.lock().unwrap();
+            logged = ops.into_iter().try_for_each(|(op, s)| {
+                log.write(&hdr::Interval {
+                    tag: Some(op),
+                    start: offset + tick.start,
+                    duration,
+                    histogram: s.histogram,
+                })
+            });
+        }
+        ticks.push(tick);
+    };
+
+    let mut next = 0;
+    while !timeline.done.load(Ordering::Acquire) {
+        let due = start + period * (next + 2);
+        let now = Instant::now();
+        if now < due {
+            thread::sleep((due - now).min(Duration::from_millis(100)));
+            continue;
+        }
+        pend(&mut pending, next, timeline.take(Some(next + 1)));
+        let ops = pending.remove(&next).unwrap_or_default();
+        if !ops.is_empty() {
+            reported(next, period, ops);
+        }
+        next += 1;
+    }
+    // the last interval ends early, with the run
+    let end = start.elapsed();
+    pend(&mut pending, next, timeline.take(None));
+    for (index, ops) in pending {
+        let duration = period.min(end.saturating_sub(period * index));
+        reported(index, duration, ops);
+    }
+    logged?;
+    Ok((started, ticks))
+}
+
+/// Adds handed over slices to the intervals still to be reported, those of
+/// intervals reported already to the `next` one.
+fn pend(
+    pending: &mut BTreeMap<u32, BTreeMap<String, Slice>>,
+    next: u32,
+    slices: Vec<(u32, HashMap<String, Slice>)>,
+) {
+    for (index, slices) in slices {
+        let tick = pending.entry(index.max(next)).or_default();
+        for (op, s) in slices {
+            tick.entry(op).or_default().merge(&s);
+        }
+    }
+}
+
+fn print_tick(t: &Tick, ps: &[f64]) {
+    for (op, s) in &t.ops {
+        let latencies: Vec<_> = ps
+            .iter()
+            .zip(&s.latencies)
+            .map(|(p, l)| format!("p{p} {:?}", Duration::from_nanos(*l)))
+            .collect();
+        println!(
+            "[{:>9.3}s] {op}: ops/sec {:.2}; {}; max {:?}; errors {}",
+            t.start.as_secs_f64(),
+            s.ops as f64 / t.duration.as_secs_f64(),
+            latencies.join(", "),
+            Duration::from_nanos(s.max),
+            s.errors
+        );
+    }
+}
+
+/// One op in one interval of the time series, latencies in microseconds.
+#[derive(Serialize)]
+struct Row<'a> {
+    run: usize,
+    time_s: f64,
+    op: &'a str,
+    ops: usize,
+    ops_per_sec: f64,
+    errors: usize,
+    latency_us: BTreeMap<String, f64>,
+    max_us: f64,
+}
+
+/// Writes the ticks of every run as a time series, JSON if `path` ends in
+/// `.json`, otherwise CSV.
+pub fn write(path: &Path, runs: &[Vec<Tick>], percentiles: &[f64]) -> io::Result<()> {
+    let mut rows = Vec::new();
+    for (run, ticks) in runs.iter().enumerate() {
+        for t in ticks {
+            for (op, s) in &t.ops {
+                let us = |ns: u64| ns as f64 / 1000.0;
+                rows.push(Row {
+                    run,
+                    time_s: t.start.as_secs_f64(),
+                    op,
+                    ops: s.ops,
+                    ops_per_sec: s.ops as f64 / t.duration.as_secs_f64(),
+                    errors: s.errors,
+                    latency_us: percentiles
+                        .iter()
+                        .zip(&s.latencies)
+                        .map(|(p, l)| (format!("p{p}"), us(*l)))
+                        .collect(),
+                    max_us: us(s.max),
+                });
+            }
+        }
+    }
+
+    let mut w = BufWriter::new(std::fs::File::create(path)?);
+    if path.extension().is_some_and(|e| e == "json") {
+        serde_json::to_writer_pretty(&mut w, &rows)?;
+        writeln!(w)?;
+    } else {
+        write!(w, "run,time_s,op,ops,ops_per_sec,errors")?;
+        for p in percentiles {
+            write!(w, ",p{p}_us")?;
+        }
+        writeln!(w, ",max_us")?;
+        for r in &rows {
+            write!(
+                w,
+                "{},{:.3},{},{},{:.2},{}",
+                r.run, r.time_s, r.op, r.ops, r.ops_per_sec, r.errors
+            )?;
+            for p in percentiles {
+                write!(w, ",{:.3}", r.latency_us[&format!("p{p}")])?;
+            }
+            writeln!(w, ",{:.3}", r.max_us)?;
+        }
+    }
+    w.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slice(ops: usize, errors: usize, latency: u64) -> Slice {
+        let mut s = Slice {
+            ops,
+            errors,
+            ..Default::default()
+        };
+        for _ in 0..ops {
+            s.histogram += latency;
+        }
+        s
+    }
+
+    fn path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("bench-interval-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn recorder() {
+        let t = Arc::new(Timeline::new(Duration::from_secs(3600)));
+        t.begin();
+        let mut r = Recorder::new(Some(t.clone()));
+        r.record("GET", Some(Duration::from_micros(1)));
+        r.record("GET", Some(Duration::from_micros(2)));
+        r.record("GET", None);
+        r.record("SET", Some(Duration::from_micros(1)));
+        // handed over once the interval ends or the worker finishes
+        assert!(t.take(None).is_empty());
+        drop(r);
+        let taken = t.take(None);
+        assert_eq!(taken.len(), 1);
+        let (index, slices) = &taken[0];
+        assert_eq!(*index, 0);
+        assert_eq!((slices["GET"].ops, slices["GET"].errors), (2, 1));
+        assert_eq!(slices["GET"].histogram.max(), 2000);
+        assert_eq!((slices["SET"].ops, slices["SET"].errors), (1, 0));
+
+        Recorder::new(None).record("GET", None);
+    }
+
+    #[test]
+    fn late_slices() {
+        let mut pending = BTreeMap::new();
+        let slices = |ops| HashMap::from([("GET".to_string(), slice(ops, 0, 1000))]);
+        pend(
+            &mut pending,
+            2,
+            vec![(0, slices(1)), (2, slices(2)), (3, slices(4))],
+        );
+        assert_eq!(pending.keys().collect::<Vec<_>>(), [&2, &3]);
+        assert_eq!(pending[&2]["GET"].ops, 3);
+        assert_eq!(pending[&3]["GET"].ops, 4);
+    }
+
+    #[test]
+    fn report() {
+        let period = Duration::from_millis(10);
+        let t = Arc::new(Timeline::new(period));
+        // a run that started 35ms ago and just finished, with nothing in its third interval
+        let started = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let start = Instant::now() - Duration::from_millis(35);
+        t.start.set((start, started)).unwrap();
+        for (index, ops, latency) in [(0, 100, 1000), (1, 200, 2000), (3, 50, 1000)] {
+            let mut slices = HashMap::from([("GET".to_string(), slice(ops, 1, latency))]);
+            t.hand_over(index, &mut slices);
+        }
+        t.finish();
+        let hlog = path("report.hlog");
+        let file = BufWriter::new(std::fs::File::create(&hlog).unwrap());
+        let log_start = started - Duration::from_secs(5);
+        let log = Arc::new(Mutex::new(hdr::Log::new(file, log_start).unwrap()));
+
+        let (from, ticks) = super::report(t, vec![50.0, 99.0], Some(log.clone())).unwrap();
+        assert_eq!(from, started);
+        let starts: Vec<_> = ticks.iter().map(|t| t.start.as_millis()).collect();
+        assert_eq!(starts, [0, 10, 30]);
+        assert_eq!((ticks[0].duration, ticks[1].duration), (period, period));
+        // the last interval ends with the run
+        assert!(ticks[2].duration >= Duration::from_millis(5) && ticks[2].duration < period);
+        assert_eq!(
+            ticks[1].ops["GET"],
+            Summary {
+                ops: 200,
+                errors: 1,
+                latencies: vec![2000, 2000],
+                max: 2000,
+            }
+        );
+
+        // the histograms are logged relative to the log's start
+        log.lock().unwrap().flush().unwrap();
+        let logged = hdr::read_log(&std::fs::read(&hlog).unwrap()).unwrap();
+        std::fs::remove_file(hlog).unwrap();
+        let offsets: Vec<_> = logged.iter().map(|i| i.start.as_millis()).collect();
+        assert_eq!(offsets, [5000, 5010, 5030]);
+        assert_eq!(logged[1].tag.as_deref(), Some("GET"));
+        assert_eq!(logged[1].histogram.recorded(), [(2000, 200)]);
+
+        let csv_path = path("report.csv");
+        write(&csv_path, &[ticks], &[50.0, 99.0]).unwrap();
+        let csv = std::fs::read_to_string(&csv_path).unwrap();
+        std::fs::remove_file(csv_path).unwrap();
+        let rows: Vec<_> = csv.lines().collect();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(
+            rows[0],
+            "run,time_s,op,ops,ops_per_sec,errors,p50_us,p99_us,max_us"
+        );
+        assert_eq!(rows[1], "0,0.000,GET,100,10000.00,1,1.000,1.000,1.000");
+        assert_eq!(rows[2], "0,0.010,GET,200,20000.00,1,2.000,2.000,2.000");
+        assert!(rows[3].starts_with("0,0.030,GET,50,"));
+    }
+
+    #[test]
+    fn write_json() {
+        let tick = |start, ops| Tick {
+            start: Duration::from_millis(start),
+            duration: Duration::from_millis(500),
+            ops: BTreeMap::from([(
+                "SET".to_string(),
+                Summary {
+                    ops,
+                    errors: 0,
+                    latencies: vec![1500, 250_000],
+                    max: 1_000_000,
+                },
+            )]),
+        };
+        let json = path("write.json");
+        let runs = [vec![tick(0, 10), tick(500, 20)], vec![tick(0, 30)]];
+        write(&json, &runs, &[50.0, 99.9]).unwrap();
+        let rows: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+        std::fs::remove_file(json).unwrap();
+        assert_eq!(rows.as_array().unwrap().len(), 3);
+        assert_eq!(
+            rows[1],
+            serde_json::json!({
+                "run": 0,
+                "time_s": 0.5,
+                "op": "SET",
+                "ops": 20,
+                "ops_per_sec": 40.0,
+                "errors": 0,
+                "latency_us": { "p50": 1.5, "p99.9": 250.0 },
+                "max_us": 1000.0,
+            })
+        );
+        assert_eq!((&rows[2]["run"], &rows[2]["ops"]), (&1.into(), &30.into()));
+    }
+}
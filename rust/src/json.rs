@@ -0,0 +1,45 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::bench::Result;
+use crate::server::Stats;
+use crate::stats::OpSummary;
+use crate::Config;
+
+/// Everything a run produced, written by `--json`.
+#[derive(Serialize)]
+struct Report<'a> {
+    config: &'a Config,
+    runs: &'a [BTreeMap<String, Result>],
+    /// With `--server-stats`, how the server's statistics moved over each run
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    server: &'a [Option<Stats>],
+    summary: &'a BTreeMap<String, OpSummary>,
+}
+
+/// Writes the config, the per-op results of every run, the server's statistics
+/// and their summary to `path`.
+pub fn write(
+    path: &Path,
+    c: &Config,
+    runs: &[BTreeMap<String, Result>],
+    server: &[Option<Stats>],
+    summary: &BTreeMap<String, OpSummary>,
+) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(
+        &mut w,
+        &Report {
+            config: c,
+            runs,
+            server,
+            summary,
+        },
+    )?;
+    writeln!(w)?;
+    w.flush()
+}
@@ -0,0 +1,170 @@
+use std::fmt::Write;
+
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Normal, Zipf};
+use serde::Serialize;
+
+use crate::Config;
+
+#[derive(clap::ValueEnum, Debug, Clone, Serialize)]
+pub enum KeyDistribution {
+    UNIFORM,
+    ZIPFIAN,
+    GAUSSIAN,
+    SEQUENTIAL,
+}
+
+enum Dist {
+    Uniform,
+    Zipfian(Zipf<f64>),
+    Gaussian(Normal<f64>),
+    Sequential(u64),
+}
+
+/// Picks keys of the form `prefix-N` from `--key-range`, or always the bare
+/// `--key` when no range is configured.
+pub struct KeyGen {
+    prefix: String,
+    range: u64,
+    dist: Dist,
+    rng: SmallRng,
+    key: String,
+}
+
+impl KeyGen {
+    pub fn new(c: &Config) -> Result<Self, String> {
+        let range = c.key_range;
+        let dist = match c.key_distribution {
+            KeyDistribution::UNIFORM => Dist::Uniform,
+            KeyDistribution::ZIPFIAN => {
+                let zipf = Zipf::new(range.max(1), c.zipf_skew)
+                    .map_err(|e| format!("invalid zipf skew: {e}"))?;
+                Dist::Zipfian(zipf)
+            }
+            KeyDistribution::GAUSSIAN => {
+                let stddev = c.key_stddev.unwrap_or(range as f64 / 6.0);
+                let normal = Normal::new(range as f64 / 2.0, stddev)
+                    .map_err(|e| format!("invalid key stddev: {e}"))?;
+                Dist::Gaussian(normal)
+            }
+            KeyDistribution::SEQUENTIAL => Dist::Sequential(0),
+        };
+        Ok(KeyGen {
+            prefix: c.key.clone(),
+            range,
+            dist,
+            rng: SmallRng::from_entropy(),
+            key: c.key.clone(),
+        })
+    }
+
+    pub fn next(&mut self) -> &str {
+        if self.range == 0 {
+            return &self.key;
+        }
+        let i = match &mut self.dist {
+            Dist::Uniform => self.rng.gen_range(0..self.range),
+            // ranks start at 1, with rank 1 the most popular
+            Dist::Zipfian(z) => z.sample(&mut self.rng) as u64 - 1,
+            Dist::Gaussian(n) => {
+                (n.sample(&mut self.rng).round().max(0.0) as u64).min(self.range - 1)
+            }
+            Dist::Sequential(next) => {
+                let i = *next;
+                *next = (i + 1) % self.range;
+                i
+            }
+        };
+        self.key.clear();
+        write!(self.key, "{}-{}", self.prefix, i).unwrap();
+        &self.key
+    }
+
+    /// Every key in the keyspace, used to pre-populate the server.
+    pub fn all(&self) -> impl Iterator<Item = String> + '_ {
+        let range = self.range;
+        (0..range.max(1)).map(move |i| {
+            if range == 0 {
+                self.prefix.clone()
+            } else {
+                format!("{}-{}", self.prefix, i)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn keys(args: &[&str]) -> KeyGen {
+        let argv = ["bench", "-k", "k"].iter().chain(args);
+        KeyGen::new(&Config::try_parse_from(argv).unwrap()).unwrap()
+    }
+
+    /// How often each key index of the range comes up in `n` keys.
+    fn counts(g: &mut KeyGen, n: usize) -> Vec<usize> {
+        let mut counts = vec![0; g.range as usize];
+        for _ in 0..n {
+            let key = g.next();
+            let i: usize = key.strip_prefix("k-").unwrap().parse().unwrap();
+            counts[i] += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn in_range() {
+        for dist in ["uniform", "zipfian", "gaussian", "sequential"] {
+            let mut g = keys(&["-K", "50", "--key-distribution", dist]);
+            let counts = counts(&mut g, 10_000);
+            assert_eq!(counts.iter().sum::<usize>(), 10_000, "{dist}");
+        }
+        // a wide gaussian is clamped to the range
+        let mut g = keys(&[
+            "-K",
+            "10",
+            "--key-distribution",
+            "gaussian",
+            "--key-stddev",
+            "100",
+        ]);
+        let counts = counts(&mut g, 10_000);
+        assert!(counts[0] > 1000 && counts[9] > 1000, "{counts:?}");
+    }
+
+    #[test]
+    fn bare_key() {
+        let mut g = keys(&["--key-distribution", "zipfian"]);
+        assert_eq!(g.next(), "k");
+        assert_eq!(g.all().collect::<Vec<_>>(), ["k"]);
+    }
+
+    #[test]
+    fn sequential() {
+        let mut g = keys(&["-K", "3", "--key-distribution", "sequential"]);
+        let keys: Vec<_> = (0..7).map(|_| g.next().to_string()).collect();
+        assert_eq!(keys, ["k-0", "k-1", "k-2", "k-0", "k-1", "k-2", "k-0"]);
+    }
+
+    #[test]
+    fn zipfian() {
+        let mut g = keys(&["-K", "100", "--key-distribution", "zipfian"]);
+        let counts = counts(&mut g, 100_000);
+        // rank 1 is about twice as popular as rank 2 and far more than the tail
+        assert!(counts[0] > counts[1] && counts[1] > counts[9], "{counts:?}");
+        assert!(counts[0] > 10 * counts[99], "{counts:?}");
+        assert!(counts[..10].iter().sum::<usize>() > 50_000, "{counts:?}");
+    }
+
+    #[test]
+    fn all() {
+        let g = keys(&["-K", "1000", "--key-distribution", "gaussian"]);
+        let all: Vec<_> = g.all().collect();
+        let unique: std::collections::BTreeSet<_> = all.iter().collect();
+        assert_eq!((all.len(), unique.len()), (1000, 1000));
+        assert_eq!((all[0].as_str(), all[999].as_str()), ("k-0", "k-999"));
+    }
+}
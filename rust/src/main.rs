@@ -1,46 +1,190 @@
+#![allow(clippy::upper_case_acronyms)]
+
 use crate::bench::*;
+use crate::data::{DataSize, Payload};
+use crate::keys::KeyDistribution;
+use crate::ops::OpMix;
 use crate::task::*;
-use clap::Parser;
-use std::collections::HashMap;
+use clap::{CommandFactory, FromArgMatches, Parser};
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::rc::Rc;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 use std::{thread, time};
 
 mod bench;
 mod client;
+mod compare;
+mod data;
 mod hdr;
+mod interval;
+mod json;
+mod keys;
+mod meta;
+mod mock;
+mod ops;
+mod redis;
+mod scenario;
+mod server;
+mod stats;
 mod task;
+#[path = "tokio/tokio.rs"]
+mod tokio_client;
+#[cfg(feature = "uring")]
+#[path = "tokio-uring/uring.rs"]
+mod uring;
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    Compare(compare::Args),
+    Scenario(scenario::Args),
+    Mock(mock::Args),
+}
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, serde::Serialize)]
 pub struct Config {
+    #[command(subcommand)]
+    #[serde(skip)]
+    command: Option<Command>,
     /// Number of full test iterations
     #[arg(short = 'x', long, default_value_t = 3)]
     runs: i64,
-    /// Number of requests per thread
+    /// Number of requests per connection
     #[arg(short = 'n', long, default_value_t = 10_000)]
     requests: i64,
-    /// Size of the data payload in bytes, specify 0 to not perform any writes
-    #[arg(short = 'd', long, default_value_t = 100_000)]
-    data: i64,
-    #[arg(skip)]
-    data_string: String,
+    /// Run for a fixed wall time instead of a number of requests (eg. 30s, 500ms, 2m)
+    #[arg(short = 'D', long, value_parser = parse_duration)]
+    #[serde(serialize_with = "opt_secs")]
+    duration: Option<Duration>,
+    /// Time to run load before samples are recorded
+    #[arg(short = 'w', long, value_parser = parse_duration, default_value = "0s")]
+    #[serde(serialize_with = "secs")]
+    warmup: Duration,
+    /// Target aggregate throughput in ops/sec; requests follow a fixed timeline
+    /// instead of waiting on the previous response
+    #[arg(short = 'R', long)]
+    rate: Option<f64>,
+    /// Arrival process for --rate (FIXED, POISSON)
+    #[arg(long, value_enum, default_value_t = Arrival::FIXED)]
+    arrival: Arrival,
+    /// Fail requests that get no response within this time (eg. 500ms), by default
+    /// wait indefinitely. RSMEM defaults to 1s, MEMRS does not support it
+    #[arg(long, value_parser = parse_duration)]
+    #[serde(serialize_with = "opt_secs")]
+    timeout: Option<Duration>,
+    /// Abort the run once more than this many requests failed; misses are
+    /// reported but not counted. By default failures never abort
+    #[arg(long)]
+    max_errors: Option<u64>,
+    /// Number of worker threads
+    #[arg(short = 'T', long, default_value_t = 1)]
+    threads: i64,
+    /// Number of connections per worker thread
+    #[arg(short = 'C', long, default_value_t = 1)]
+    connections: i64,
+    /// Size of the data payload in bytes, specify 0 to not perform any writes.
+    /// Optionally a range (eg. 100-1000), a normal distribution (normal:MEAN:STDDEV)
+    /// or weighted buckets from a file of `SIZE WEIGHT` lines (@FILE)
+    #[arg(short = 'd', long, value_parser = DataSize::parse, default_value = "100000")]
+    data: DataSize,
     #[arg(skip)]
-    data_bytes: Vec<u8>,
+    #[serde(skip)]
+    data_bytes: data::Values,
+    /// Payload contents (FILL, RANDOM, CORPUS)
+    #[arg(short = 'P', long, value_enum, default_value_t = Payload::FILL)]
+    payload: Payload,
+    /// Fraction of a RANDOM payload that compresses away (0 = incompressible)
+    #[arg(long, default_value_t = 0.0)]
+    compressibility: f64,
+    /// Directory of sample values for CORPUS payloads
+    #[arg(long)]
+    corpus: Option<PathBuf>,
     /// Optional chunk size in bytes
     #[arg(short = 'c', long)]
     chunk_size: Option<i64>,
     /// Ratio of ops (eg. 0.1 == 10% sets && 90% gets)
     #[arg(short = 'r', long, default_value_t = 0.1)]
     ratio: f64,
+    /// Weighted mix of commands for the BASIC, META and REDIS clients, overriding --ratio
+    /// (eg. get:8,set:1,incr:1). One of SET, GET, ADD, REPLACE, APPEND, PREPEND, CAS,
+    /// GETS, DELETE, INCR, DECR, TOUCH, GAT, MGET; APPEND/PREPEND grow their values.
+    /// REDIS supports SET, GET, DELETE, INCR, DECR, MGET and its own MSET
+    #[arg(long, value_parser = OpMix::parse)]
+    ops: Option<OpMix>,
+    /// Number of requests the BASIC client writes before reading their responses
+    #[arg(long, default_value_t = 1)]
+    pipeline: usize,
+    /// Number of keys fetched by each MGET (a pipelined quiet mg batch for META)
+    /// and stored by each REDIS MSET
+    #[arg(long, default_value_t = 10)]
+    mget_keys: usize,
+    /// Extra flags for every META get, eg. "c t" to return CAS and TTL
+    #[arg(long, default_value = "")]
+    meta_flags: String,
+    /// Switch REDIS connections to RESP3 with HELLO 3
+    #[arg(long)]
+    resp3: bool,
     /// Key/prefix to use
     #[arg(short = 'k', long, default_value = "lol")]
     key: String,
-    /// Client type to use (MEMRS, RSMEM, BASIC)
+    /// Number of keys (prefix-0..prefix-N), 0 to only use the bare key
+    #[arg(short = 'K', long, default_value_t = 0)]
+    key_range: u64,
+    /// Key access distribution over the key range (UNIFORM, ZIPFIAN, GAUSSIAN, SEQUENTIAL)
+    #[arg(long, value_enum, default_value_t = KeyDistribution::UNIFORM)]
+    key_distribution: KeyDistribution,
+    /// Skew of the ZIPFIAN key distribution
+    #[arg(long, default_value_t = 0.99)]
+    zipf_skew: f64,
+    /// Standard deviation of the GAUSSIAN key distribution, defaults to range/6
+    #[arg(long)]
+    key_stddev: Option<f64>,
+    /// Client type to use (MEMRS, RSMEM, BASIC, META, REDIS, TOKIO, URING)
     #[arg(short = 't', long, value_enum, default_value_t = ClientType::MEMRS)]
     client_type: ClientType,
-    /// output prefix for hdrHistogram files
+    /// Execution engine (THREAD for blocking clients, TOKIO/URING for async clients)
+    #[arg(short = 'e', long, value_enum, default_value_t = Engine::THREAD)]
+    engine: Engine,
+    /// Print per-op throughput and latency every interval of a run (eg. 1s, 500ms)
+    #[arg(long, value_parser = parse_duration)]
+    #[serde(serialize_with = "opt_secs")]
+    interval: Option<Duration>,
+    /// Write the --interval time series of every run to this file, as JSON if it
+    /// ends in .json and CSV otherwise
+    #[arg(long, requires = "interval")]
+    interval_out: Option<PathBuf>,
+    /// output prefix for hdrHistogram files: percentile tables of all runs and an
+    /// interval log (PREFIX_rs.hlog) of every run, or of every --interval
     #[arg(short = 'o', long)]
     out: Option<String>,
+    /// Latency percentiles to print (eg. 99.9 or 50,99,99.99)
+    #[arg(long, value_delimiter = ',', value_parser = parse_percentile, default_value = "50,90,99,99.9")]
+    percentiles: Vec<f64>,
+    /// Latencies below this are not told apart (eg. 1ns, 1us)
+    #[arg(long, value_parser = parse_duration, default_value = "1ns")]
+    #[serde(serialize_with = "secs")]
+    histogram_min: Duration,
+    /// Latencies above this are recorded as this
+    #[arg(long, value_parser = parse_duration, default_value = "60s")]
+    #[serde(serialize_with = "secs")]
+    histogram_max: Duration,
+    /// Significant digits kept by the latency histograms (0-5)
+    #[arg(long, default_value_t = 3)]
+    histogram_digits: u8,
+    /// Print the merged percentiles of each op in these interval logs instead of
+    /// running a benchmark, eg. to combine logs from several machines
+    #[arg(long, num_args = 1..)]
+    read_log: Vec<PathBuf>,
+    /// Write the config and every run's per-op results, including the encoded
+    /// histograms, as JSON to this file
+    #[arg(long)]
+    json: Option<PathBuf>,
+    /// Read the server's stats (memcached `stats` and `stats slabs`, Redis
+    /// `INFO`) before and after each run and report how they changed
+    #[arg(long)]
+    server_stats: bool,
     /// Server address
     #[arg(short = 's', long, default_value = "127.0.0.1")]
     server: String,
@@ -55,60 +199,313 @@ pub struct Config {
     socket: Option<String>,
 }
 
-fn main() -> std::io::Result<()> {
-    let mut c = Config::parse();
-    c.data_string = std::iter::repeat("x")
-        .take(c.data as usize)
-        .collect::<Vec<_>>()
-        .join("");
-    c.data_bytes = c.data_string.bytes().collect::<Vec<_>>();
-    let c = Rc::new(c);
-
-    let (mut min_map, mut max_map) = (
-        HashMap::<String, Rc<Result>>::new(),
-        HashMap::<String, Rc<Result>>::new(),
+fn parse_duration(s: &str) -> std::result::Result<Duration, String> {
+    let (n, unit) = s.split_at(
+        s.find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(s.len()),
     );
+    let n: f64 = n.parse().map_err(|_| format!("invalid duration: {s}"))?;
+    let secs = match unit {
+        "ns" => n / 1_000_000_000.0,
+        "us" | "µs" => n / 1_000_000.0,
+        "ms" => n / 1000.0,
+        "" | "s" => n,
+        "m" => n * 60.0,
+        _ => return Err(format!("invalid duration unit: {unit}")),
+    };
+    Ok(Duration::from_secs_f64(secs))
+}
+
+fn parse_percentile(s: &str) -> std::result::Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(p) if p > 0.0 && p <= 100.0 => Ok(p),
+        _ => Err(format!("invalid percentile: {s}, expected (0, 100]")),
+    }
+}
+
+fn secs<S: serde::Serializer>(d: &Duration, s: S) -> std::result::Result<S::Ok, S::Error> {
+    s.serialize_f64(d.as_secs_f64())
+}
+
+fn opt_secs<S: serde::Serializer>(
+    d: &Option<Duration>,
+    s: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match d {
+        Some(d) => secs(d, s),
+        None => s.serialize_none(),
+    }
+}
+
+/// Merges the intervals of every log by tag and prints their percentiles, writing
+/// them to `--out` files like a run's results.
+fn read_logs(c: &Config) -> std::io::Result<()> {
+    let mut merged = BTreeMap::<String, hdr::HDR>::new();
+    for path in &c.read_log {
+        for i in hdr::read_log(&std::fs::read(path)?)? {
+            let tag = i.tag.unwrap_or_else(|| "untagged".to_string());
+            *merged.entry(tag).or_default() += &i.histogram;
+        }
+    }
+    for (tag, h) in merged {
+        println!("TAG: {tag}");
+        h.percentiles(std::io::stdout())?;
+        if let Some(out) = &c.out {
+            h.percentiles(File::create(out.clone() + "_rs_" + &tag)?)?;
+        }
+    }
+    Ok(())
+}
+
+/// The options given on the command line, as scenario options.
+fn given(matches: &clap::ArgMatches) -> serde_json::Map<String, serde_json::Value> {
+    use clap::parser::ValueSource;
+    use serde_json::Value;
+    Config::command()
+        .get_arguments()
+        .filter(|a| matches.value_source(a.get_id().as_str()) == Some(ValueSource::CommandLine))
+        .map(|a| {
+            let id = a.get_id().as_str();
+            let value = match matches.get_raw(id) {
+                Some(raw) if a.get_action().takes_values() => Value::Array(
+                    raw.map(|v| Value::String(v.to_string_lossy().into_owned()))
+                        .collect(),
+                ),
+                _ => Value::Bool(true),
+            };
+            (id.to_string(), value)
+        })
+        .collect()
+}
+
+fn main() -> std::io::Result<()> {
+    let matches = Config::command().get_matches();
+    let c = Config::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    hdr::configure(c.histogram_min, c.histogram_max, c.histogram_digits)?;
+    match &c.command {
+        Some(Command::Compare(args)) => return compare::run(args, &c.percentiles),
+        Some(Command::Mock(args)) => return mock::run(args),
+        Some(Command::Scenario(args)) => {
+            // every cell is checked before the first one runs
+            let mut cells = Vec::new();
+            for cell in scenario::cells(args, given(&matches))? {
+                let c = Config::try_parse_from(&cell.args).map_err(|e| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("cell {}: {e}", cell.name),
+                    )
+                })?;
+                cells.push((cell.name, prepare(c)?));
+            }
+            for (name, c) in cells {
+                println!("=================== CELL: {name} ===================");
+                bench(c)?;
+            }
+            return Ok(());
+        }
+        None => (),
+    }
+    if !c.read_log.is_empty() {
+        return read_logs(&c);
+    }
+    bench(prepare(c)?)
+}
+
+/// Validates the options that depend on each other and generates the payload.
+fn prepare(mut c: Config) -> std::io::Result<Arc<Config>> {
+    if c.threads < 1 || c.connections < 1 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "--threads {} and --connections {} must both be at least 1",
+                c.threads, c.connections
+            ),
+        ));
+    }
+    if !(0.0..=1.0).contains(&c.ratio) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("--ratio {} is not between 0 and 1", c.ratio),
+        ));
+    }
+    // every task builds its own generators, so check their parameters once here
+    if let Err(e) = keys::KeyGen::new(&c).and_then(|_| data::SizeGen::new(&c.data)) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, e));
+    }
+    if !c.engine.supports(&c.client_type) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "client type {:?} cannot run on engine {:?}",
+                c.client_type, c.engine
+            ),
+        ));
+    }
+    // memcached-rs has no way to give up on a request
+    if c.timeout.is_some() && matches!(c.client_type, ClientType::MEMRS) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "client type MEMRS does not support --timeout",
+        ));
+    }
+    if c.pipeline == 0 || c.pipeline > 1 && !matches!(c.client_type, ClientType::BASIC) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "--pipeline {} is not supported by client type {:?}",
+                c.pipeline, c.client_type
+            ),
+        ));
+    }
+    if let Some(op) = c
+        .ops
+        .iter()
+        .flat_map(|m| m.ops())
+        .find(|op| !c.client_type.supports(**op))
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "client type {:?} does not support {}",
+                c.client_type,
+                op.name()
+            ),
+        ));
+    }
+    c.data_bytes = data::payload(
+        &c.payload,
+        c.data.max(),
+        c.compressibility,
+        c.corpus.as_deref(),
+    )?;
+    Ok(Arc::new(c))
+}
+
+/// Runs the benchmark `--runs` times, then reports and writes the results. A
+/// run aborted over `--max-errors` ends the benchmark once the runs so far are
+/// written out.
+fn bench(c: Arc<Config>) -> std::io::Result<()> {
+    let mut runs = Vec::new();
+    let mut series = Vec::new();
+    let mut servers = Vec::new();
+    let mut aborted = None;
+    let log = match &c.out {
+        Some(out) => {
+            let file = BufWriter::new(File::create(out.clone() + "_rs.hlog")?);
+            Some(Arc::new(Mutex::new(hdr::Log::new(
+                file,
+                SystemTime::now(),
+            )?)))
+        }
+        None => None,
+    };
 
     for i in 0..c.runs {
         println!("RUN: {i}");
         let mut b = Bench::new(c.clone());
-        b.run();
-        for (op, r) in b.result() {
-            min_map
-                .entry(op.clone())
-                .and_modify(|e| {
-                    if r.opsps < e.opsps {
-                        *e = r.clone();
-                    }
-                })
-                .or_insert(r.clone());
-
-            max_map
-                .entry(op.clone())
-                .and_modify(|e| {
-                    if r.opsps > e.opsps {
-                        *e = r.clone();
-                    }
-                })
-                .or_insert(r.clone());
+        match &log {
+            // with --interval the log holds the time series instead of whole runs
+            Some(log) if c.interval.is_some() => b.log_intervals(log.clone()),
+            _ => (),
+        }
+        // a run aborted over --max-errors still reports what it completed
+        let run = b.run();
+        let ticks = b.take_ticks();
+        if let (Some(log), None) = (&log, c.interval) {
+            let mut log = log.lock().unwrap();
+            let (started, elapsed) = b.span();
+            let start = started.duration_since(log.start()).unwrap_or_default();
+            for (op, r) in b.result() {
+                let mut interval = |tag: String, histogram: &hdr::HDR| {
+                    log.write(&hdr::Interval {
+                        tag: Some(tag),
+                        start,
+                        duration: elapsed,
+                        histogram: histogram.clone(),
+                    })
+                };
+                interval(op.clone(), &r.histogram)?;
+                if !r.corrected.is_empty() {
+                    interval(op.clone() + "_corrected", &r.corrected)?;
+                }
+            }
+        }
+        series.extend(ticks.map(|(_, ticks)| ticks));
+        if c.server_stats {
+            servers.push(b.server_stats().cloned());
+        }
+        runs.push(
+            b.result()
+                .into_iter()
+                .map(|(op, r)| (op.clone(), (*r).clone()))
+                .collect::<BTreeMap<_, _>>(),
+        );
+        if let Err(e) = run {
+            aborted = Some(e);
+            break;
         }
         thread::sleep(time::Duration::from_secs(1));
     }
 
     println!("~~~~~~~~~~~~~~~~~~~RESULTS~~~~~~~~~~~~~~~~");
-    println!("\nWORST RESULT:");
-    for (op, r) in min_map {
-        println!("OP: {op} \n {r}");
+    for (i, run) in runs.iter().enumerate() {
+        println!("\nRUN {i}:");
+        for (op, r) in run {
+            println!("OP: {op} \n {r}");
+        }
+        if let Some(Some(s)) = servers.get(i) {
+            println!("SERVER: \n{s}");
+        }
     }
 
-    println!("\nBEST RESULT:");
-    for (op, r) in max_map {
-        println!("OP: {op} \n {r}");
+    let summary = stats::OpSummary::of(&runs, &c.percentiles);
+    println!("\nSUMMARY:");
+    for (op, s) in &summary {
+        println!("OP: {op} \n{s}");
         if let Some(out) = &c.out {
-            let file = File::create(out.clone() + "_rs_" + &op)?;
-            r.histogram.percentiles(file)?;
-            // r.histogram.serialize(file);
+            let file = File::create(out.clone() + "_rs_" + op)?;
+            s.histogram.percentiles(file)?;
+            if !s.corrected.is_empty() {
+                let file = File::create(out.clone() + "_rs_" + op + "_corrected")?;
+                s.corrected.percentiles(file)?;
+            }
         }
     }
-    Ok(())
+    if let Some(log) = &log {
+        log.lock().unwrap().flush()?;
+    }
+    if let Some(path) = &c.interval_out {
+        interval::write(path, &series, &c.percentiles)?;
+    }
+    if let Some(path) = &c.json {
+        json::write(path, &c, &runs, &servers, &summary)?;
+    }
+    aborted.map_or(Ok(()), Err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Asserts `prepare` rejects the command line `args` as invalid input.
+    fn rejected(args: &[&str]) {
+        let argv = std::iter::once("bench").chain(args.iter().copied());
+        match prepare(Config::try_parse_from(argv).unwrap()) {
+            Ok(_) => panic!("{args:?} was accepted"),
+            Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput, "{args:?}"),
+        }
+    }
+
+    #[test]
+    fn prepare_rejects() {
+        rejected(&["-T", "0"]);
+        rejected(&["-C", "0"]);
+        rejected(&["-C=-1"]);
+        rejected(&["-t", "basic", "-r", "1.5"]);
+        rejected(&["-r=-0.1"]);
+        rejected(&["-d", "normal:100:inf"]);
+        rejected(&["--key-distribution", "zipfian", "--zipf-skew=-1"]);
+        rejected(&["--key-distribution", "gaussian", "--key-stddev", "NaN"]);
+        rejected(&["-t", "memrs", "--timeout", "1s"]);
+    }
 }
@@ -0,0 +1,236 @@
+use std::io::{self, Write};
+
+use std::time::Duration;
+
+use crate::client::{self, ReadBuf, Stream};
+
+/// Status code of a meta response.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Status {
+    /// `VA`: hit with a value
+    Value,
+    /// `HD`: success without a value
+    #[default]
+    Hit,
+    /// `EN`: miss
+    Miss,
+    /// `NS`: not stored
+    NotStored,
+    /// `EX`: CAS mismatch
+    Exists,
+    /// `NF`: not found
+    NotFound,
+    /// `MN`: end of a pipelined batch
+    NoOp,
+}
+
+/// A meta response with its return flags decoded.
+#[allow(dead_code)]
+#[derive(Debug, Default)]
+pub struct Meta {
+    pub status: Status,
+    pub value: Option<Vec<u8>>,
+    /// `c` flag
+    pub cas: Option<u64>,
+    /// `t` flag, -1 for items that never expire
+    pub ttl: Option<i64>,
+    /// `O` flag
+    pub opaque: Option<String>,
+    /// `k` flag
+    pub key: Option<String>,
+    /// `W`: this client won the right to recache the item
+    pub win: bool,
+    /// `X`: the item is stale
+    pub stale: bool,
+    /// `Z`: another client already won the recache
+    pub won: bool,
+}
+
+/// Client for the memcached meta protocol. Request flags are passed through as
+/// written, eg. `"v c t"` or `"q O123"`.
+pub struct MetaClient {
+    stream: Stream,
+    wbuf: Vec<u8>,
+    rbuf: ReadBuf,
+}
+
+impl MetaClient {
+    pub fn connect(addr: &str) -> io::Result<Self> {
+        Ok(Self {
+            stream: Stream::connect(addr)?,
+            wbuf: Vec::new(),
+            rbuf: ReadBuf::default(),
+        })
+    }
+
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_timeout(timeout)
+    }
+
+    /// Meta get; pass `v` to fetch the value.
+    pub fn mg(&mut self, key: &str, flags: &str) -> io::Result<Meta> {
+        self.wbuf.clear();
+        line(&mut self.wbuf, "mg", key, None, flags);
+        self.send()?;
+        self.response()
+    }
+
+    /// Meta set.
+    pub fn ms(&mut self, key: &str, value: &[u8], flags: &str) -> io::Result<Meta> {
+        self.wbuf.clear();
+        line(&mut self.wbuf, "ms", key, Some(value.len()), flags);
+        self.wbuf.extend_from_slice(value);
+        self.wbuf.extend_from_slice(b"\r\n");
+        self.send()?;
+        self.response()
+    }
+
+    /// Meta delete; `I` marks the item stale instead of removing it.
+    pub fn md(&mut self, key: &str, flags: &str) -> io::Result<Meta> {
+        self.wbuf.clear();
+        line(&mut self.wbuf, "md", key, None, flags);
+        self.send()?;
+        self.response()
+    }
+
+    /// Meta arithmetic; `v` returns the new value.
+    pub fn ma(&mut self, key: &str, flags: &str) -> io::Result<Meta> {
+        self.wbuf.clear();
+        line(&mut self.wbuf, "ma", key, None, flags);
+        self.send()?;
+        self.response()
+    }
+
+    /// Pipelines a quiet `mg` per key followed by `mn` in a single write. Misses
+    /// are suppressed by the server, so only hits are returned, each tagged with
+    /// the index of its key as opaque.
+    pub fn mg_batch(&mut self, keys: &[&str], flags: &str) -> io::Result<Vec<Meta>> {
+        self.wbuf.clear();
+        for (i, key) in keys.iter().enumerate() {
+            line(&mut self.wbuf, "mg", key, None, &format!("{flags} q O{i}"));
+        }
+        self.wbuf.extend_from_slice(b"mn\r\n");
+        self.send()?;
+
+        let mut hits = Vec::new();
+        loop {
+            let m = self.response()?;
+            if m.status == Status::NoOp {
+                return Ok(hits);
+            }
+            hits.push(m);
+        }
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        self.stream.write_all(&self.wbuf)?;
+        self.stream.flush()
+    }
+
+    fn response(&mut self) -> io::Result<Meta> {
+        let header = self.rbuf.read_line(&mut self.stream)?;
+        let invalid = || client::unexpected(header);
+        let mut parts = header.split(' ').filter(|p| !p.is_empty());
+
+        let mut m = Meta::default();
+        let mut size = None;
+        m.status = match parts.next() {
+            Some("VA") => {
+                size = Some(
+                    parts
+                        .next()
+                        .and_then(|s| s.parse::<usize>().ok())
+                        .ok_or_else(invalid)?,
+                );
+                Status::Value
+            }
+            Some("HD") => Status::Hit,
+            Some("EN") => Status::Miss,
+            Some("NS") => Status::NotStored,
+            Some("EX") => Status::Exists,
+            Some("NF") => Status::NotFound,
+            Some("MN") => Status::NoOp,
+            _ => return Err(invalid()),
+        };
+        for flag in parts {
+            let mut chars = flag.chars();
+            // flags are a single letter, then their token if any
+            let f = chars
+                .next()
+                .filter(char::is_ascii_alphabetic)
+                .ok_or_else(invalid)?;
+            let token = chars.as_str();
+            match f {
+                'c' => m.cas = Some(token.parse().map_err(|_| invalid())?),
+                't' => m.ttl = Some(token.parse().map_err(|_| invalid())?),
+                'O' => m.opaque = Some(token.to_string()),
+                'k' => m.key = Some(token.to_string()),
+                'W' => m.win = true,
+                'X' => m.stale = true,
+                'Z' => m.won = true,
+                _ => (),
+            }
+        }
+        if let Some(size) = size {
+            m.value = Some(self.rbuf.read_value(&mut self.stream, size)?);
+        }
+        Ok(m)
+    }
+}
+
+/// Appends `<cmd> <key> [<datalen>] <flags>\r\n` to `buf`.
+fn line(buf: &mut Vec<u8>, cmd: &str, key: &str, datalen: Option<usize>, flags: &str) {
+    buf.extend_from_slice(cmd.as_bytes());
+    buf.push(b' ');
+    buf.extend_from_slice(key.as_bytes());
+    if let Some(len) = datalen {
+        buf.extend_from_slice(format!(" {len}").as_bytes());
+    }
+    for flag in flags.split(' ').filter(|f| !f.is_empty()) {
+        buf.push(b' ');
+        buf.extend_from_slice(flag.as_bytes());
+    }
+    buf.extend_from_slice(b"\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// A client of a server that sends `reply` whatever it is asked.
+    fn client(reply: &'static [u8]) -> MetaClient {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut s, _) = listener.accept().unwrap();
+            s.write_all(reply).unwrap();
+            io::copy(&mut s, &mut io::sink()).unwrap();
+        });
+        MetaClient::connect(&addr.to_string()).unwrap()
+    }
+
+    #[test]
+    fn flags() {
+        let mut c = client(b"VA 2 c42 t-1 Ofoo kbar W X Z\r\nhi\r\n");
+        let m = c.mg("bar", "v c t O k").unwrap();
+        assert_eq!(m.status, Status::Value);
+        assert_eq!(m.value.as_deref(), Some(&b"hi"[..]));
+        assert_eq!((m.cas, m.ttl), (Some(42), Some(-1)));
+        assert_eq!(
+            (m.opaque.as_deref(), m.key.as_deref()),
+            (Some("foo"), Some("bar"))
+        );
+        assert!(m.win && m.stale && m.won);
+    }
+
+    #[test]
+    fn malformed_flags() {
+        for reply in ["HD \u{e9}1\r\n", "HD 1\r\n", "HD cx\r\n", "HD t\r\n"] {
+            let mut c = client(reply.as_bytes());
+            let e = c.mg("k", "c t").unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{reply:?}");
+        }
+    }
+}
@@ -0,0 +1,1299 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const VERSION: &str = "1.6.0-bench";
+/// Longest key memcached accepts
+const KEY_MAX: usize = 250;
+/// Expiration times up to 30 days are relative, anything above a unix time
+const RELATIVE_MAX: i64 = 60 * 60 * 24 * 30;
+/// Largest binary protocol request body read before dropping the connection
+const BODY_MAX: u32 = 1 << 30;
+
+/// Serve an in-memory memcached to test the clients against or measure their overhead
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Address to listen on
+    #[arg(short = 's', long, default_value = "127.0.0.1")]
+    listen: String,
+    /// TCP port to listen on, 0 for any free port
+    #[arg(short = 'p', long, default_value_t = 11211)]
+    port: u16,
+    /// Also listen on this UNIX domain socket
+    #[arg(short = 'S', long)]
+    socket: Option<PathBuf>,
+}
+
+/// Serves until the process is killed.
+pub fn run(args: &Args) -> io::Result<()> {
+    let server = Server::new();
+    let addr = server.listen_tcp(&format!("{}:{}", args.listen, args.port))?;
+    println!("listening on {addr}");
+    if let Some(path) = &args.socket {
+        server.listen_unix(path)?;
+        println!("listening on {}", path.display());
+    }
+    loop {
+        thread::park();
+    }
+}
+
+/// An in-memory memcached speaking the text, meta and binary protocols, told
+/// apart by the first byte of each connection. Items never get evicted; every
+/// connection has a thread of its own and all of them share one lock.
+#[derive(Clone)]
+pub struct Server {
+    shared: Arc<Shared>,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Server {
+            shared: Arc::new(Shared {
+                store: Mutex::new(Store::default()),
+                started: Instant::now(),
+                curr_connections: AtomicU64::new(0),
+                total_connections: AtomicU64::new(0),
+                bytes_read: AtomicU64::new(0),
+                bytes_written: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Accepts TCP connections in the background, returning the bound address,
+    /// eg. to find the port picked for `127.0.0.1:0`.
+    pub fn listen_tcp(&self, addr: &str) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let shared = self.shared.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let _ = stream.set_nodelay(true);
+                if let Ok(reader) = stream.try_clone() {
+                    shared.spawn(reader, stream);
+                }
+            }
+        });
+        Ok(addr)
+    }
+
+    /// Accepts connections on a UNIX domain socket in the background, replacing
+    /// a socket left over at `path`.
+    pub fn listen_unix(&self, path: &Path) -> io::Result<()> {
+        if fs::metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        let shared = self.shared.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Ok(reader) = stream.try_clone() {
+                    shared.spawn(reader, stream);
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// State shared by every connection of a server.
+struct Shared {
+    store: Mutex<Store>,
+    started: Instant,
+    curr_connections: AtomicU64,
+    total_connections: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+impl Shared {
+    /// Serves a connection on a thread of its own until the client disconnects.
+    fn spawn<R, W>(self: &Arc<Self>, reader: R, writer: W)
+    where
+        R: Read + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        let shared = self.clone();
+        thread::spawn(move || {
+            shared.curr_connections.fetch_add(1, Ordering::Relaxed);
+            shared.total_connections.fetch_add(1, Ordering::Relaxed);
+            // a broken connection only ends itself
+            let _ = connection(reader, writer, &shared);
+            shared.curr_connections.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+
+    fn store(&self) -> std::sync::MutexGuard<'_, Store> {
+        self.store.lock().unwrap()
+    }
+
+    /// `stats`, or `stats slabs`; `None` for any other group.
+    fn stats(&self, group: Option<&str>) -> Option<Vec<(String, String)>> {
+        let store = self.store();
+        let bytes: usize = store
+            .items
+            .iter()
+            .map(|(k, i)| k.len() + i.value.len())
+            .sum();
+        let load = |n: &AtomicU64| n.load(Ordering::Relaxed).to_string();
+        let stats = match group {
+            None => {
+                let mut stats = vec![
+                    ("pid", std::process::id().to_string()),
+                    ("uptime", self.started.elapsed().as_secs().to_string()),
+                    ("time", unix_time().to_string()),
+                    ("version", VERSION.to_string()),
+                    ("curr_connections", load(&self.curr_connections)),
+                    ("total_connections", load(&self.total_connections)),
+                ];
+                stats.extend(
+                    COUNTERS
+                        .iter()
+                        .map(|c| (*c, store.counters.get(c).to_string())),
+                );
+                stats.extend([
+                    ("bytes_read", load(&self.bytes_read)),
+                    ("bytes_written", load(&self.bytes_written)),
+                    ("curr_items", store.items.len().to_string()),
+                    ("bytes", bytes.to_string()),
+                    ("evictions", "0".to_string()),
+                ]);
+                stats
+            }
+            Some("slabs") => vec![
+                (
+                    "active_slabs",
+                    usize::from(!store.items.is_empty()).to_string(),
+                ),
+                ("total_malloced", bytes.to_string()),
+            ],
+            Some(_) => return None,
+        };
+        Some(
+            stats
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+        )
+    }
+}
+
+/// Counters reported by `stats`, in its order.
+const COUNTERS: &[&str] = &[
+    "cmd_get",
+    "cmd_set",
+    "cmd_flush",
+    "cmd_touch",
+    "get_hits",
+    "get_misses",
+    "get_expired",
+    "delete_misses",
+    "delete_hits",
+    "incr_misses",
+    "incr_hits",
+    "decr_misses",
+    "decr_hits",
+    "cas_misses",
+    "cas_hits",
+    "cas_badval",
+    "touch_hits",
+    "touch_misses",
+    "total_items",
+];
+
+#[derive(Default)]
+struct Counters(HashMap<&'static str, u64>);
+
+impl Counters {
+    fn add(&mut self, name: &'static str) {
+        *self.0.entry(name).or_default() += 1;
+    }
+
+    fn get(&self, name: &str) -> u64 {
+        self.0.get(name).copied().unwrap_or_default()
+    }
+}
+
+#[derive(Clone)]
+struct Item {
+    value: Vec<u8>,
+    flags: u32,
+    cas: u64,
+    expires: Option<Instant>,
+}
+
+impl Item {
+    /// Seconds left to live, -1 for items that never expire.
+    fn ttl(&self) -> i64 {
+        self.expires.map_or(-1, |e| {
+            e.saturating_duration_since(Instant::now()).as_secs() as i64
+        })
+    }
+}
+
+/// Converts a memcached expiration time to a deadline: 0 never expires and
+/// negative times already have.
+fn expiry(exptime: i64) -> Option<Instant> {
+    let now = Instant::now();
+    match exptime {
+        0 => None,
+        e if e < 0 => Some(now),
+        e if e <= RELATIVE_MAX => Some(now + Duration::from_secs(e as u64)),
+        e => Some(now + Duration::from_secs((e - unix_time()).max(0) as u64)),
+    }
+}
+
+fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Set,
+    Add,
+    Replace,
+    Append,
+    Prepend,
+}
+
+/// The outcome of a storage command or delete.
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    /// Done, with the item's new CAS unique
+    Done(u64),
+    NotStored,
+    /// CAS mismatch
+    Exists,
+    NotFound,
+}
+
+/// The outcome of incr/decr.
+enum Arith {
+    Done(Item),
+    NotFound,
+    Exists,
+    NonNumeric,
+}
+
+/// The commands of every protocol, on the items of a server.
+#[derive(Default)]
+struct Store {
+    items: HashMap<Vec<u8>, Item>,
+    last_cas: u64,
+    counters: Counters,
+}
+
+/// Looks `key` up, dropping it if it expired.
+fn live<'a>(items: &'a mut HashMap<Vec<u8>, Item>, key: &[u8]) -> Option<&'a mut Item> {
+    let now = Instant::now();
+    if items
+        .get(key)
+        .is_some_and(|i| i.expires.is_some_and(|e| e <= now))
+    {
+        items.remove(key);
+    }
+    items.get_mut(key)
+}
+
+impl Store {
+    fn next_cas(&mut self) -> u64 {
+        self.last_cas += 1;
+        self.last_cas
+    }
+
+    /// Fetches a copy of the item, first setting its expiration time to `touch`.
+    fn get(&mut self, key: &[u8], touch: Option<i64>) -> Option<Item> {
+        self.counters.add("cmd_get");
+        if touch.is_some() {
+            self.counters.add("cmd_touch");
+        }
+        match live(&mut self.items, key) {
+            Some(item) => {
+                if let Some(exptime) = touch {
+                    item.expires = expiry(exptime);
+                    self.counters.add("touch_hits");
+                }
+                self.counters.add("get_hits");
+                Some(item.clone())
+            }
+            None => {
+                if touch.is_some() {
+                    self.counters.add("touch_misses");
+                }
+                self.counters.add("get_misses");
+                None
+            }
+        }
+    }
+
+    /// Stores `value`, if the item's CAS unique still is `cas` when given.
+    /// Appending and prepending keep the flags and expiration time.
+    fn store(
+        &mut self,
+        mode: Mode,
+        key: &[u8],
+        value: Vec<u8>,
+        flags: u32,
+        exptime: i64,
+        cas: Option<u64>,
+    ) -> Outcome {
+        self.counters.add("cmd_set");
+        let next = self.next_cas();
+        let current = live(&mut self.items, key);
+        if let Some(cas) = cas {
+            match &current {
+                None => {
+                    self.counters.add("cas_misses");
+                    return Outcome::NotFound;
+                }
+                Some(i) if i.cas != cas => {
+                    self.counters.add("cas_badval");
+                    return Outcome::Exists;
+                }
+                Some(_) => self.counters.add("cas_hits"),
+            }
+        }
+        match (mode, current) {
+            (Mode::Add, Some(_)) => return Outcome::NotStored,
+            (Mode::Replace | Mode::Append | Mode::Prepend, None) => return Outcome::NotStored,
+            (Mode::Append, Some(item)) => {
+                item.value.extend_from_slice(&value);
+                item.cas = next;
+            }
+            (Mode::Prepend, Some(item)) => {
+                item.value.splice(0..0, value);
+                item.cas = next;
+            }
+            _ => {
+                let item = Item {
+                    value,
+                    flags,
+                    cas: next,
+                    expires: expiry(exptime),
+                };
+                self.items.insert(key.to_vec(), item);
+            }
+        }
+        self.counters.add("total_items");
+        Outcome::Done(next)
+    }
+
+    fn delete(&mut self, key: &[u8], cas: Option<u64>) -> Outcome {
+        match live(&mut self.items, key) {
+            None => {
+                self.counters.add("delete_misses");
+                Outcome::NotFound
+            }
+            Some(item) if cas.is_some_and(|c| c != item.cas) => Outcome::Exists,
+            Some(_) => {
+                self.items.remove(key);
+                self.counters.add("delete_hits");
+                Outcome::Done(0)
+            }
+        }
+    }
+
+    /// Adds `delta` to a decimal value, wrapping on incr and stopping at 0 on
+    /// decr. A missing item is created with `vivify`'s initial value and
+    /// expiration time when given.
+    fn arith(
+        &mut self,
+        key: &[u8],
+        incr: bool,
+        delta: u64,
+        vivify: Option<(u64, i64)>,
+        cas: Option<u64>,
+    ) -> Arith {
+        let (hits, misses) = if incr {
+            ("incr_hits", "incr_misses")
+        } else {
+            ("decr_hits", "decr_misses")
+        };
+        let next = self.next_cas();
+        let Some(item) = live(&mut self.items, key) else {
+            self.counters.add(misses);
+            let Some((initial, exptime)) = vivify else {
+                return Arith::NotFound;
+            };
+            let item = Item {
+                value: initial.to_string().into_bytes(),
+                flags: 0,
+                cas: next,
+                expires: expiry(exptime),
+            };
+            self.items.insert(key.to_vec(), item.clone());
+            return Arith::Done(item);
+        };
+        if cas.is_some_and(|c| c != item.cas) {
+            return Arith::Exists;
+        }
+        let Some(n) = std::str::from_utf8(&item.value)
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+        else {
+            return Arith::NonNumeric;
+        };
+        let n = if incr {
+            n.wrapping_add(delta)
+        } else {
+            n.saturating_sub(delta)
+        };
+        item.value = n.to_string().into_bytes();
+        item.cas = next;
+        let item = item.clone();
+        self.counters.add(hits);
+        Arith::Done(item)
+    }
+
+    fn touch(&mut self, key: &[u8], exptime: i64) -> bool {
+        self.counters.add("cmd_touch");
+        match live(&mut self.items, key) {
+            Some(item) => {
+                item.expires = expiry(exptime);
+                self.counters.add("touch_hits");
+                true
+            }
+            None => {
+                self.counters.add("touch_misses");
+                false
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        self.counters.add("cmd_flush");
+        self.items.clear();
+    }
+}
+
+/// Counts the bytes that go through a connection for `stats`.
+struct Metered<'a, S>(S, &'a AtomicU64);
+
+impl<S: Read> Read for Metered<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.0.read(buf)?;
+        self.1.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+impl<S: Write> Write for Metered<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.0.write(buf)?;
+        self.1.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+fn connection(reader: impl Read, writer: impl Write, shared: &Shared) -> io::Result<()> {
+    let mut r = BufReader::new(Metered(reader, &shared.bytes_read));
+    let mut w = BufWriter::new(Metered(writer, &shared.bytes_written));
+    let binary = r.fill_buf()?.first() == Some(&binary::REQUEST);
+    loop {
+        let open = if binary {
+            binary::request(&mut r, &mut w, shared)?
+        } else {
+            text_request(&mut r, &mut w, shared)?
+        };
+        if !open {
+            return w.flush();
+        }
+        // answer pipelined requests in a single write once the client waits
+        if r.buffer().is_empty() {
+            w.flush()?;
+        }
+    }
+}
+
+const BAD_FORMAT: &[u8] = b"CLIENT_ERROR bad command line format\r\n";
+const NON_NUMERIC: &[u8] = b"CLIENT_ERROR cannot increment or decrement non-numeric value\r\n";
+
+fn num<T: FromStr>(token: Option<&&str>) -> Option<T> {
+    token.and_then(|t| t.parse().ok())
+}
+
+/// Reads a value of `len` bytes and its terminator.
+fn read_data(r: &mut impl BufRead, len: usize) -> io::Result<Option<Vec<u8>>> {
+    let mut data = vec![0; len + 2];
+    r.read_exact(&mut data)?;
+    if !data.ends_with(b"\r\n") {
+        return Ok(None);
+    }
+    data.truncate(len);
+    Ok(Some(data))
+}
+
+/// Reads and answers one text or meta protocol request, returning false once
+/// the client is gone or sent `quit`.
+fn text_request(r: &mut impl BufRead, w: &mut impl Write, shared: &Shared) -> io::Result<bool> {
+    let mut line = Vec::new();
+    if r.read_until(b'\n', &mut line)? == 0 {
+        return Ok(false);
+    }
+    let line = String::from_utf8_lossy(&line);
+    let tokens: Vec<&str> = line.split_ascii_whitespace().collect();
+    let Some((&cmd, args)) = tokens.split_first() else {
+        w.write_all(b"ERROR\r\n")?;
+        return Ok(true);
+    };
+    let key_ok = |k: &&str| k.len() <= KEY_MAX;
+    let storage = matches!(
+        cmd,
+        "set" | "add" | "replace" | "append" | "prepend" | "cas" | "ms"
+    );
+    if !args.iter().all(key_ok) || storage && args.is_empty() {
+        // the client may still send the value, which then reads as an unknown command
+        w.write_all(BAD_FORMAT)?;
+        return Ok(true);
+    }
+    match cmd {
+        "quit" => return Ok(false),
+        "mg" | "ms" | "md" | "ma" | "mn" => meta::request(cmd, args, r, w, shared)?,
+        _ => text(cmd, args, r, w, shared)?,
+    }
+    Ok(true)
+}
+
+fn text<W: Write>(
+    cmd: &str,
+    args: &[&str],
+    r: &mut impl BufRead,
+    w: &mut W,
+    shared: &Shared,
+) -> io::Result<()> {
+    let noreply = args.last() == Some(&"noreply");
+    let reply = |w: &mut W, msg: &[u8]| if noreply { Ok(()) } else { w.write_all(msg) };
+    match cmd {
+        "get" | "gets" | "gat" | "gats" => {
+            let (touch, keys) = match cmd {
+                "gat" | "gats" => match num::<i64>(args.first()) {
+                    Some(exptime) => (Some(exptime), &args[1..]),
+                    None => return w.write_all(BAD_FORMAT),
+                },
+                _ => (None, args),
+            };
+            if keys.is_empty() {
+                return w.write_all(b"ERROR\r\n");
+            }
+            let items: Vec<_> = {
+                let mut store = shared.store();
+                keys.iter()
+                    .filter_map(|k| store.get(k.as_bytes(), touch).map(|i| (k, i)))
+                    .collect()
+            };
+            for (key, item) in items {
+                write!(w, "VALUE {key} {} {}", item.flags, item.value.len())?;
+                if cmd.ends_with('s') {
+                    write!(w, " {}", item.cas)?;
+                }
+                w.write_all(b"\r\n")?;
+                w.write_all(&item.value)?;
+                w.write_all(b"\r\n")?;
+            }
+            w.write_all(b"END\r\n")
+        }
+        "set" | "add" | "replace" | "append" | "prepend" | "cas" => {
+            let mode = match cmd {
+                "add" => Mode::Add,
+                "replace" => Mode::Replace,
+                "append" => Mode::Append,
+                "prepend" => Mode::Prepend,
+                _ => Mode::Set,
+            };
+            let (Some(flags), Some(exptime), Some(len)) =
+                (num(args.get(1)), num(args.get(2)), num(args.get(3)))
+            else {
+                return w.write_all(BAD_FORMAT);
+            };
+            let cas = match cmd {
+                "cas" => match num(args.get(4)) {
+                    Some(cas) => Some(cas),
+                    None => return w.write_all(BAD_FORMAT),
+                },
+                _ => None,
+            };
+            let Some(value) = read_data(r, len)? else {
+                return w.write_all(b"CLIENT_ERROR bad data chunk\r\n");
+            };
+            let outcome =
+                shared
+                    .store()
+                    .store(mode, args[0].as_bytes(), value, flags, exptime, cas);
+            reply(
+                w,
+                match outcome {
+                    Outcome::Done(_) => b"STORED\r\n",
+                    Outcome::NotStored => b"NOT_STORED\r\n",
+                    Outcome::Exists => b"EXISTS\r\n",
+                    Outcome::NotFound => b"NOT_FOUND\r\n",
+                },
+            )
+        }
+        "delete" => {
+            let Some(key) = args.first() else {
+                return w.write_all(b"ERROR\r\n");
+            };
+            match shared.store().delete(key.as_bytes(), None) {
+                Outcome::Done(_) => reply(w, b"DELETED\r\n"),
+                _ => reply(w, b"NOT_FOUND\r\n"),
+            }
+        }
+        "incr" | "decr" => {
+            let (Some(key), Some(delta)) = (args.first(), num(args.get(1))) else {
+                return w.write_all(b"CLIENT_ERROR invalid numeric delta argument\r\n");
+            };
+            match shared
+                .store()
+                .arith(key.as_bytes(), cmd == "incr", delta, None, None)
+            {
+                Arith::Done(item) => {
+                    let mut msg = item.value;
+                    msg.extend_from_slice(b"\r\n");
+                    reply(w, &msg)
+                }
+                Arith::NonNumeric => reply(w, NON_NUMERIC),
+                Arith::NotFound | Arith::Exists => reply(w, b"NOT_FOUND\r\n"),
+            }
+        }
+        "touch" => {
+            let (Some(key), Some(exptime)) = (args.first(), num(args.get(1))) else {
+                return w.write_all(BAD_FORMAT);
+            };
+            match shared.store().touch(key.as_bytes(), exptime) {
+                true => reply(w, b"TOUCHED\r\n"),
+                false => reply(w, b"NOT_FOUND\r\n"),
+            }
+        }
+        // a delayed flush takes effect right away
+        "flush_all" => {
+            shared.store().flush();
+            reply(w, b"OK\r\n")
+        }
+        "verbosity" => reply(w, b"OK\r\n"),
+        "version" => write!(w, "VERSION {VERSION}\r\n"),
+        "stats" => match shared.stats(args.first().copied()) {
+            Some(stats) => {
+                for (name, value) in stats {
+                    write!(w, "STAT {name} {value}\r\n")?;
+                }
+                w.write_all(b"END\r\n")
+            }
+            None => w.write_all(b"ERROR\r\n"),
+        },
+        _ => w.write_all(b"ERROR\r\n"),
+    }
+}
+
+mod meta {
+    use super::*;
+
+    /// Flags of a meta request, eg. `v`, `T30` or `Oabc`.
+    struct Flags<'a>(&'a [&'a str]);
+
+    impl Flags<'_> {
+        fn has(&self, flag: char) -> bool {
+            self.0.iter().any(|f| f.starts_with(flag))
+        }
+
+        fn token(&self, flag: char) -> Option<&str> {
+            self.0.iter().find_map(|f| f.strip_prefix(flag))
+        }
+
+        fn number<T: FromStr>(&self, flag: char) -> Option<T> {
+            self.token(flag).and_then(|t| t.parse().ok())
+        }
+
+        /// The return flags asked for, in the order they were: the opaque and
+        /// the key always, the item's only when there is one.
+        fn returned(&self, key: &str, item: Option<&Item>) -> String {
+            let mut ret = String::new();
+            for f in self.0 {
+                let (flag, token) = f.split_at(1);
+                let field = match (flag, item) {
+                    ("O", _) => format!(" O{token}"),
+                    ("k", _) => format!(" k{key}"),
+                    ("c", Some(i)) => format!(" c{}", i.cas),
+                    ("f", Some(i)) => format!(" f{}", i.flags),
+                    ("s", Some(i)) => format!(" s{}", i.value.len()),
+                    ("t", Some(i)) => format!(" t{}", i.ttl()),
+                    _ => continue,
+                };
+                ret.push_str(&field);
+            }
+            ret
+        }
+    }
+
+    fn value(w: &mut impl Write, value: &[u8], ret: &str) -> io::Result<()> {
+        write!(w, "VA {}{ret}\r\n", value.len())?;
+        w.write_all(value)?;
+        w.write_all(b"\r\n")
+    }
+
+    /// Answers `mg`, `ms`, `md`, `ma` and `mn`. The quiet flag `q` hides EN on
+    /// `mg`, HD on `ms` and `ma`, and HD and NF on `md`.
+    pub(super) fn request(
+        cmd: &str,
+        args: &[&str],
+        r: &mut impl BufRead,
+        w: &mut impl Write,
+        shared: &Shared,
+    ) -> io::Result<()> {
+        if cmd == "mn" {
+            return w.write_all(b"MN\r\n");
+        }
+        let Some((&key, rest)) = args.split_first() else {
+            return w.write_all(BAD_FORMAT);
+        };
+        // the flags of ms follow the length of its value
+        let flags = Flags(match cmd {
+            "ms" => rest.get(1..).unwrap_or_default(),
+            _ => rest,
+        });
+        let quiet = flags.has('q');
+        match cmd {
+            "mg" => {
+                let item = shared.store().get(key.as_bytes(), flags.number('T'));
+                let ret = flags.returned(key, item.as_ref());
+                match item {
+                    Some(item) if flags.has('v') => value(w, &item.value, &ret),
+                    Some(_) => write!(w, "HD{ret}\r\n"),
+                    None if quiet => Ok(()),
+                    None => write!(w, "EN{ret}\r\n"),
+                }
+            }
+            "ms" => {
+                let Some(len) = num(rest.first()) else {
+                    return w.write_all(b"CLIENT_ERROR bad data chunk\r\n");
+                };
+                let Some(data) = read_data(r, len)? else {
+                    return w.write_all(b"CLIENT_ERROR bad data chunk\r\n");
+                };
+                let mode = match flags.token('M').map(|m| m.to_ascii_uppercase()).as_deref() {
+                    None | Some("S") => Mode::Set,
+                    Some("E") => Mode::Add,
+                    Some("R") => Mode::Replace,
+                    Some("A") => Mode::Append,
+                    Some("P") => Mode::Prepend,
+                    _ => return w.write_all(b"CLIENT_ERROR invalid mode for ms\r\n"),
+                };
+                let outcome = shared.store().store(
+                    mode,
+                    key.as_bytes(),
+                    data,
+                    flags.number('F').unwrap_or(0),
+                    flags.number('T').unwrap_or(0),
+                    flags.number('C'),
+                );
+                let mut ret = flags.returned(key, None);
+                let status = match outcome {
+                    Outcome::Done(_) if quiet => return Ok(()),
+                    Outcome::Done(cas) => {
+                        if flags.has('c') {
+                            ret.push_str(&format!(" c{cas}"));
+                        }
+                        "HD"
+                    }
+                    Outcome::NotStored => "NS",
+                    Outcome::Exists => "EX",
+                    Outcome::NotFound => "NF",
+                };
+                write!(w, "{status}{ret}\r\n")
+            }
+            "md" => {
+                let ret = flags.returned(key, None);
+                match shared.store().delete(key.as_bytes(), flags.number('C')) {
+                    Outcome::Done(_) | Outcome::NotFound if quiet => Ok(()),
+                    Outcome::Done(_) => write!(w, "HD{ret}\r\n"),
+                    Outcome::Exists => write!(w, "EX{ret}\r\n"),
+                    _ => write!(w, "NF{ret}\r\n"),
+                }
+            }
+            "ma" => {
+                let incr = match flags.token('M').map(|m| m.to_ascii_uppercase()).as_deref() {
+                    None | Some("I") | Some("+") => true,
+                    Some("D") | Some("-") => false,
+                    _ => return w.write_all(b"CLIENT_ERROR invalid mode for ma\r\n"),
+                };
+                let vivify = flags
+                    .number('N')
+                    .map(|ttl| (flags.number('J').unwrap_or(0), ttl));
+                let outcome = shared.store().arith(
+                    key.as_bytes(),
+                    incr,
+                    flags.number('D').unwrap_or(1),
+                    vivify,
+                    flags.number('C'),
+                );
+                match outcome {
+                    Arith::Done(item) => {
+                        let ret = flags.returned(key, Some(&item));
+                        if flags.has('v') {
+                            value(w, &item.value, &ret)
+                        } else if quiet {
+                            Ok(())
+                        } else {
+                            write!(w, "HD{ret}\r\n")
+                        }
+                    }
+                    Arith::NotFound => write!(w, "NF{}\r\n", flags.returned(key, None)),
+                    Arith::Exists => write!(w, "EX{}\r\n", flags.returned(key, None)),
+                    Arith::NonNumeric => w.write_all(NON_NUMERIC),
+                }
+            }
+            _ => w.write_all(b"ERROR\r\n"),
+        }
+    }
+}
+
+mod binary {
+    use super::*;
+
+    pub const REQUEST: u8 = 0x80;
+    const RESPONSE: u8 = 0x81;
+
+    const GET: u8 = 0x00;
+    const SET: u8 = 0x01;
+    const ADD: u8 = 0x02;
+    const REPLACE: u8 = 0x03;
+    const DELETE: u8 = 0x04;
+    const INCREMENT: u8 = 0x05;
+    const DECREMENT: u8 = 0x06;
+    const QUIT: u8 = 0x07;
+    const FLUSH: u8 = 0x08;
+    const GETQ: u8 = 0x09;
+    const NOOP: u8 = 0x0a;
+    const VERSION: u8 = 0x0b;
+    const GETK: u8 = 0x0c;
+    const GETKQ: u8 = 0x0d;
+    const APPEND: u8 = 0x0e;
+    const PREPEND: u8 = 0x0f;
+    const STAT: u8 = 0x10;
+    const APPENDQ: u8 = 0x19;
+    const PREPENDQ: u8 = 0x1a;
+    const TOUCH: u8 = 0x1c;
+    const GAT: u8 = 0x1d;
+    const GATQ: u8 = 0x1e;
+    const GATK: u8 = 0x23;
+    const GATKQ: u8 = 0x24;
+
+    const NO_ERROR: u16 = 0x00;
+    const KEY_NOT_FOUND: u16 = 0x01;
+    const KEY_EXISTS: u16 = 0x02;
+    const INVALID_ARGUMENTS: u16 = 0x04;
+    const NOT_STORED: u16 = 0x05;
+    const NON_NUMERIC: u16 = 0x06;
+    const UNKNOWN_COMMAND: u16 = 0x81;
+
+    /// The opcode of the request's loud variant, and whether it was quiet.
+    fn loud(opcode: u8) -> (u8, bool) {
+        match opcode {
+            GETQ => (GET, true),
+            GETKQ => (GETK, true),
+            // SETQ up to FLUSHQ
+            0x11..=0x18 => (opcode - 0x10, true),
+            APPENDQ => (APPEND, true),
+            PREPENDQ => (PREPEND, true),
+            GATQ => (GAT, true),
+            GATKQ => (GATK, true),
+            op => (op, false),
+        }
+    }
+
+    struct Response<'a> {
+        opcode: u8,
+        status: u16,
+        opaque: u32,
+        cas: u64,
+        extras: &'a [u8],
+        key: &'a [u8],
+        value: &'a [u8],
+    }
+
+    impl Response<'_> {
+        fn write(&self, w: &mut impl Write) -> io::Result<()> {
+            let body = self.extras.len() + self.key.len() + self.value.len();
+            w.write_all(&[RESPONSE, self.opcode])?;
+            w.write_all(&(self.key.len() as u16).to_be_bytes())?;
+            w.write_all(&[self.extras.len() as u8, 0])?;
+            w.write_all(&self.status.to_be_bytes())?;
+            w.write_all(&(body as u32).to_be_bytes())?;
+            w.write_all(&self.opaque.to_be_bytes())?;
+            w.write_all(&self.cas.to_be_bytes())?;
+            w.write_all(self.extras)?;
+            w.write_all(self.key)?;
+            w.write_all(self.value)
+        }
+    }
+
+    fn u32_at(b: &[u8], at: usize) -> u32 {
+        u32::from_be_bytes(b[at..at + 4].try_into().unwrap())
+    }
+
+    fn u64_at(b: &[u8], at: usize) -> u64 {
+        u64::from_be_bytes(b[at..at + 8].try_into().unwrap())
+    }
+
+    /// Reads and answers one binary protocol request, returning false once the
+    /// client is gone or sent quit. Quiet requests only answer misses and
+    /// errors, except quiet gets which answer hits only.
+    pub(super) fn request(
+        r: &mut impl BufRead,
+        w: &mut impl Write,
+        shared: &Shared,
+    ) -> io::Result<bool> {
+        let mut header = [0; 24];
+        match r.read_exact(&mut header) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+            r => r?,
+        }
+        let body_len = u32_at(&header, 8);
+        if header[0] != REQUEST || body_len > BODY_MAX {
+            return Err(ErrorKind::InvalidData.into());
+        }
+        let mut body = vec![0; body_len as usize];
+        r.read_exact(&mut body)?;
+
+        let (opcode, quiet) = loud(header[1]);
+        let key_len = u16::from_be_bytes([header[2], header[3]]) as usize;
+        let extras_len = header[4] as usize;
+        let mut res = Response {
+            opcode: header[1],
+            status: NO_ERROR,
+            opaque: u32_at(&header, 12),
+            cas: 0,
+            extras: &[],
+            key: &[],
+            value: &[],
+        };
+        if extras_len + key_len > body.len() || key_len > KEY_MAX {
+            res.status = INVALID_ARGUMENTS;
+            res.value = b"Invalid arguments";
+            res.write(w)?;
+            return Ok(true);
+        }
+        let (extras, rest) = body.split_at(extras_len);
+        let (key, value) = rest.split_at(key_len);
+        let cas = Some(u64_at(&header, 16)).filter(|c| *c != 0);
+
+        // the owned parts of a response, borrowed by `res`
+        let flags;
+        let item;
+        let number;
+        let stats;
+        match (opcode, extras.len()) {
+            (GET | GETK, 0) | (GAT | GATK, 4) => {
+                let touch = (extras.len() == 4).then(|| u32_at(extras, 0) as i64);
+                item = shared.store().get(key, touch);
+                let with_key = matches!(opcode, GETK | GATK);
+                match &item {
+                    Some(item) => {
+                        flags = item.flags.to_be_bytes();
+                        res.extras = &flags;
+                        res.cas = item.cas;
+                        res.value = &item.value;
+                        if with_key {
+                            res.key = key;
+                        }
+                    }
+                    None if quiet => return Ok(true),
+                    None => {
+                        res.status = KEY_NOT_FOUND;
+                        if with_key {
+                            res.key = key;
+                        } else {
+                            res.value = b"Not found";
+                        }
+                    }
+                }
+            }
+            (SET | ADD | REPLACE, 8) | (APPEND | PREPEND, 0) => {
+                let mode = match opcode {
+                    ADD => Mode::Add,
+                    REPLACE => Mode::Replace,
+                    APPEND => Mode::Append,
+                    PREPEND => Mode::Prepend,
+                    _ => Mode::Set,
+                };
+                let (f, exptime) = match extras.len() {
+                    8 => (u32_at(extras, 0), u32_at(extras, 4) as i64),
+                    _ => (0, 0),
+                };
+                match shared
+                    .store()
+                    .store(mode, key, value.to_vec(), f, exptime, cas)
+                {
+                    Outcome::Done(_) if quiet => return Ok(true),
+                    Outcome::Done(cas) => res.cas = cas,
+                    Outcome::NotStored => match mode {
+                        Mode::Add => res.status = KEY_EXISTS,
+                        Mode::Replace => res.status = KEY_NOT_FOUND,
+                        _ => res.status = NOT_STORED,
+                    },
+                    Outcome::Exists => res.status = KEY_EXISTS,
+                    Outcome::NotFound => res.status = KEY_NOT_FOUND,
+                }
+            }
+            (DELETE, 0) => match shared.store().delete(key, cas) {
+                Outcome::Done(_) if quiet => return Ok(true),
+                Outcome::Done(_) => (),
+                Outcome::Exists => res.status = KEY_EXISTS,
+                _ => res.status = KEY_NOT_FOUND,
+            },
+            (INCREMENT | DECREMENT, 20) => {
+                let exptime = u32_at(extras, 16);
+                let vivify = (exptime != u32::MAX).then(|| (u64_at(extras, 8), exptime as i64));
+                let outcome =
+                    shared
+                        .store()
+                        .arith(key, opcode == INCREMENT, u64_at(extras, 0), vivify, cas);
+                match outcome {
+                    Arith::Done(_) if quiet => return Ok(true),
+                    Arith::Done(item) => {
+                        let n: u64 = String::from_utf8_lossy(&item.value).parse().unwrap_or(0);
+                        number = n.to_be_bytes();
+                        res.value = &number;
+                        res.cas = item.cas;
+                    }
+                    Arith::NotFound => res.status = KEY_NOT_FOUND,
+                    Arith::Exists => res.status = KEY_EXISTS,
+                    Arith::NonNumeric => res.status = NON_NUMERIC,
+                }
+            }
+            (TOUCH, 4) => {
+                if !shared.store().touch(key, u32_at(extras, 0) as i64) {
+                    res.status = KEY_NOT_FOUND;
+                }
+            }
+            (QUIT, _) => {
+                if !quiet {
+                    res.write(w)?;
+                }
+                return Ok(false);
+            }
+            (FLUSH, _) => {
+                shared.store().flush();
+                if quiet {
+                    return Ok(true);
+                }
+            }
+            (NOOP, _) => (),
+            (VERSION, _) => res.value = super::VERSION.as_bytes(),
+            (STAT, _) => {
+                let group = (!key.is_empty()).then(|| String::from_utf8_lossy(key));
+                stats = shared.stats(group.as_deref());
+                let Some(stats) = &stats else {
+                    res.status = KEY_NOT_FOUND;
+                    res.write(w)?;
+                    return Ok(true);
+                };
+                // one response per stat, then an empty one
+                for (name, value) in stats {
+                    res.key = name.as_bytes();
+                    res.value = value.as_bytes();
+                    res.write(w)?;
+                }
+                res.key = &[];
+                res.value = &[];
+            }
+            (GET | GETK | GAT | GATK | SET | ADD | REPLACE | APPEND | PREPEND, _)
+            | (DELETE | INCREMENT | DECREMENT | TOUCH, _) => {
+                res.status = INVALID_ARGUMENTS;
+                res.value = b"Invalid arguments";
+            }
+            _ => {
+                res.status = UNKNOWN_COMMAND;
+                res.value = b"Unknown command";
+            }
+        }
+        res.write(w)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use memcached::proto::{MultiOperation, Operation, ProtoType};
+
+    use super::*;
+    use crate::client::{Client, Store as Stored};
+    use crate::meta::{MetaClient, Status};
+
+    fn start() -> SocketAddr {
+        Server::new().listen_tcp("127.0.0.1:0").unwrap()
+    }
+
+    #[test]
+    fn text() {
+        let mut c = Client::connect(&start().to_string()).unwrap();
+        assert_eq!(c.get("k").unwrap(), None);
+        assert_eq!(c.add("k", b"1", 0, 0).unwrap(), Stored::Stored);
+        assert_eq!(c.add("k", b"2", 0, 0).unwrap(), Stored::NotStored);
+        assert_eq!(c.replace("missing", b"2", 0, 0).unwrap(), Stored::NotStored);
+        assert_eq!(c.incr("k", 41).unwrap(), Some(42));
+        assert_eq!(c.decr("k", 100).unwrap(), Some(0));
+        assert_eq!(c.incr("missing", 1).unwrap(), None);
+        assert_eq!(c.append("k", b"1").unwrap(), Stored::Stored);
+        assert_eq!(c.prepend("k", b"9").unwrap(), Stored::Stored);
+
+        let (value, cas) = c.gets("k").unwrap().unwrap();
+        assert_eq!(value, b"901");
+        assert_eq!(c.cas("k", b"x", 0, 0, cas + 1).unwrap(), Stored::Exists);
+        assert_eq!(c.cas("k", b"x", 0, 0, cas).unwrap(), Stored::Stored);
+        assert_eq!(c.cas("missing", b"x", 0, 0, cas).unwrap(), Stored::NotFound);
+        assert!(c.incr("k", 1).is_err());
+
+        assert!(c.touch("k", 100).unwrap());
+        assert_eq!(c.gat(0, "k").unwrap().as_deref(), Some(&b"x"[..]));
+        let items = c.get_multi(&["k", "missing", "k"]).unwrap();
+        assert_eq!(items.len(), 2);
+        assert!(c.delete("k").unwrap());
+        assert!(!c.delete("k").unwrap());
+
+        // a unix time in the past has already expired
+        c.set("gone", b"v", 0, RELATIVE_MAX as u32 + 1).unwrap();
+        assert_eq!(c.get("gone").unwrap(), None);
+
+        let stats: HashMap<_, _> = c.stats(None).unwrap().into_iter().collect();
+        assert_eq!(stats["curr_items"], "0");
+        assert_eq!(stats["delete_hits"], "1");
+        assert_eq!(stats["curr_connections"], "1");
+        assert!(c.stats(Some("slabs")).is_ok());
+    }
+
+    #[test]
+    fn meta() {
+        let mut c = MetaClient::connect(&start().to_string()).unwrap();
+        assert_eq!(c.mg("k", "v").unwrap().status, Status::Miss);
+        assert_eq!(c.ms("k", b"v1", "T0 F5").unwrap().status, Status::Hit);
+        let m = c.mg("k", "v c t k Oabc").unwrap();
+        assert_eq!(m.status, Status::Value);
+        assert_eq!(m.value.as_deref(), Some(&b"v1"[..]));
+        assert_eq!(m.ttl, Some(-1));
+        assert_eq!(m.key.as_deref(), Some("k"));
+        assert_eq!(m.opaque.as_deref(), Some("abc"));
+
+        let cas = m.cas.unwrap();
+        let stale = format!("C{}", cas + 1);
+        assert_eq!(c.ms("k", b"v2", &stale).unwrap().status, Status::Exists);
+        assert_eq!(c.ms("k", b"v2", "ME").unwrap().status, Status::NotStored);
+        assert_eq!(c.ms("k", b"2", "MA").unwrap().status, Status::Hit);
+        assert_eq!(c.mg("k", "v").unwrap().value.as_deref(), Some(&b"v12"[..]));
+
+        c.ms("n", b"5", "").unwrap();
+        let m = c.ma("n", "v MD D2").unwrap();
+        assert_eq!(m.value.as_deref(), Some(&b"3"[..]));
+        assert_eq!(c.ma("missing", "").unwrap().status, Status::NotFound);
+        let m = c.ma("vivified", "N0 J10 v").unwrap();
+        assert_eq!(m.value.as_deref(), Some(&b"10"[..]));
+
+        // quiet misses and stores are left out of a batch
+        let hits = c.mg_batch(&["k", "missing", "n"], "v").unwrap();
+        let opaques: Vec<_> = hits.iter().map(|m| m.opaque.as_deref()).collect();
+        assert_eq!(opaques, [Some("0"), Some("2")]);
+
+        assert_eq!(c.md("k", "").unwrap().status, Status::Hit);
+        assert_eq!(c.md("k", "").unwrap().status, Status::NotFound);
+    }
+
+    #[test]
+    fn binary() {
+        let addr = format!("tcp://{}", start());
+        let mut c = memcached::Client::connect(&[(addr, 1)], ProtoType::Binary).unwrap();
+        c.set(b"k", b"v", 3, 0).unwrap();
+        assert_eq!(c.get(b"k").unwrap(), (b"v".to_vec(), 3));
+        assert!(c.add(b"k", b"w", 0, 0).is_err());
+        assert!(c.replace(b"missing", b"w", 0, 0).is_err());
+        c.append(b"k", b"2").unwrap();
+        assert_eq!(c.get(b"k").unwrap().0, b"v2");
+
+        // created with the initial value, then incremented
+        assert_eq!(c.increment(b"n", 5, 10, 0).unwrap(), 10);
+        assert_eq!(c.increment(b"n", 5, 10, 0).unwrap(), 15);
+
+        let kv = BTreeMap::from([
+            (&b"a"[..], (&b"1"[..], 0, 0)),
+            (&b"b"[..], (&b"2"[..], 0, 0)),
+        ]);
+        c.set_multi(kv).unwrap();
+        let items = c.get_multi(&[b"a", b"missing", b"b"]).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[&b"b"[..]].0, b"2");
+
+        c.delete(b"k").unwrap();
+        assert!(c.get(b"k").is_err());
+    }
+
+    #[test]
+    fn binary_quiet() {
+        let mut s = std::net::TcpStream::connect(start()).unwrap();
+        // a quiet set is only answered by the noop after it
+        let mut req = vec![
+            binary::REQUEST,
+            0x11,
+            0,
+            1,
+            8,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            10,
+            0,
+            0,
+            0,
+            7,
+        ];
+        req.extend([0; 16]);
+        req.extend(b"kv");
+        req.extend([
+            binary::REQUEST,
+            0x0a,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            8,
+        ]);
+        req.extend([0; 8]);
+        s.write_all(&req).unwrap();
+        let mut res = [0; 24];
+        s.read_exact(&mut res).unwrap();
+        assert_eq!((res[0], res[1], res[15]), (0x81, 0x0a, 8));
+    }
+}
@@ -0,0 +1,192 @@
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+/// Commands the `BASIC`, `META` and `REDIS` tasks can benchmark, each recorded
+/// under its own histogram. Not every client supports every op, see
+/// `ClientType::supports`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    SET,
+    GET,
+    ADD,
+    REPLACE,
+    APPEND,
+    PREPEND,
+    CAS,
+    GETS,
+    DELETE,
+    INCR,
+    DECR,
+    TOUCH,
+    GAT,
+    MGET,
+    MSET,
+}
+
+impl Op {
+    const ALL: [Op; 15] = [
+        Op::SET,
+        Op::GET,
+        Op::ADD,
+        Op::REPLACE,
+        Op::APPEND,
+        Op::PREPEND,
+        Op::CAS,
+        Op::GETS,
+        Op::DELETE,
+        Op::INCR,
+        Op::DECR,
+        Op::TOUCH,
+        Op::GAT,
+        Op::MGET,
+        Op::MSET,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Op::SET => "SET",
+            Op::GET => "GET",
+            Op::ADD => "ADD",
+            Op::REPLACE => "REPLACE",
+            Op::APPEND => "APPEND",
+            Op::PREPEND => "PREPEND",
+            Op::CAS => "CAS",
+            Op::GETS => "GETS",
+            Op::DELETE => "DELETE",
+            Op::INCR => "INCR",
+            Op::DECR => "DECR",
+            Op::TOUCH => "TOUCH",
+            Op::GAT => "GAT",
+            Op::MGET => "MGET",
+            Op::MSET => "MSET",
+        }
+    }
+}
+
+/// Weighted mix of ops accepted by `--ops`, eg. `get:8,set:1,incr:1`.
+#[derive(Debug, Clone)]
+pub struct OpMix {
+    ops: Vec<Op>,
+    /// Kept alongside the index, which cannot give them back, for reporting
+    weights: Vec<f64>,
+    index: WeightedIndex<f64>,
+}
+
+impl OpMix {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut ops = Vec::new();
+        let mut weights = Vec::new();
+        for part in s.split(',') {
+            let (name, weight) = part.split_once(':').unwrap_or((part, "1"));
+            let op = Op::ALL
+                .into_iter()
+                .find(|op| op.name().eq_ignore_ascii_case(name.trim()))
+                .ok_or_else(|| format!("unknown op: {name}"))?;
+            ops.push(op);
+            weights.push(
+                weight
+                    .trim()
+                    .parse::<f64>()
+                    .map_err(|_| format!("invalid weight: {weight}"))?,
+            );
+        }
+        Self::new(ops, weights)
+    }
+
+    /// The default `--ratio` driven mix of sets and gets.
+    pub fn ratio(ratio: f64) -> Self {
+        Self::new(vec![Op::SET, Op::GET], vec![ratio, 1.0 - ratio])
+            .expect("--ratio is checked to be within [0, 1] at startup")
+    }
+
+    fn new(ops: Vec<Op>, weights: Vec<f64>) -> Result<Self, String> {
+        let index = WeightedIndex::new(&weights).map_err(|e| format!("invalid op weights: {e}"))?;
+        Ok(OpMix {
+            ops,
+            weights,
+            index,
+        })
+    }
+
+    pub fn contains(&self, op: Op) -> bool {
+        self.ops.contains(&op)
+    }
+
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    pub fn pick<R: Rng>(&self, rng: &mut R) -> Op {
+        self.ops[self.index.sample(rng)]
+    }
+}
+
+/// Serializes as a map of op name to weight.
+impl Serialize for OpMix {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let mut m = s.serialize_map(Some(self.ops.len()))?;
+        for (op, weight) in self.ops.iter().zip(&self.weights) {
+            m.serialize_entry(op.name(), weight)?;
+        }
+        m.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn parse() {
+        let mix = OpMix::parse("get:8, SET:1.5,incr").unwrap();
+        assert_eq!(mix.ops(), [Op::GET, Op::SET, Op::INCR]);
+        assert_eq!(mix.weights, [8.0, 1.5, 1.0]);
+        assert!(mix.contains(Op::INCR) && !mix.contains(Op::DECR));
+        assert_eq!(OpMix::parse("mget").unwrap().ops(), [Op::MGET]);
+    }
+
+    #[test]
+    fn rejects() {
+        assert_eq!(OpMix::parse("get,foo:1").unwrap_err(), "unknown op: foo");
+        assert_eq!(OpMix::parse("get:x").unwrap_err(), "invalid weight: x");
+        assert!(OpMix::parse("get:0,set:0").is_err());
+        assert!(OpMix::parse("get:-1,set:2").is_err());
+        assert!(OpMix::parse("").is_err());
+    }
+
+    #[test]
+    fn frequencies() {
+        let mix = OpMix::parse("get:6,set:3,delete:1,incr:0").unwrap();
+        let mut rng = SmallRng::seed_from_u64(7);
+        let mut counts = [0usize; 4];
+        for _ in 0..100_000 {
+            let op = mix.pick(&mut rng);
+            counts[mix.ops().iter().position(|o| *o == op).unwrap()] += 1;
+        }
+        for (count, expected) in counts.into_iter().zip([60_000, 30_000, 10_000, 0]) {
+            assert!(count.abs_diff(expected) < 1000, "{counts:?}");
+        }
+
+        let mut sets = 0;
+        for _ in 0..10_000 {
+            sets += (OpMix::ratio(0.1).pick(&mut rng) == Op::SET) as usize;
+        }
+        assert!(sets.abs_diff(1000) < 200, "{sets}");
+    }
+
+    #[test]
+    fn serialize() {
+        let mix = OpMix::parse("get:8,set:1,incr:0.5").unwrap();
+        assert_eq!(
+            serde_json::to_string(&mix).unwrap(),
+            r#"{"GET":8.0,"SET":1.0,"INCR":0.5}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&OpMix::ratio(0.25)).unwrap(),
+            r#"{"SET":0.25,"GET":0.75}"#
+        );
+    }
+}
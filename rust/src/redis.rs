@@ -0,0 +1,248 @@
+use std::io::{self, ErrorKind, Write};
+use std::time::Duration;
+use std::{error, fmt};
+
+use crate::client::{ReadBuf, Stream};
+
+/// A decoded RESP2/RESP3 reply.
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Nil,
+    Simple(String),
+    Int(i64),
+    Bulk(Vec<u8>),
+    Array(Vec<Value>),
+    Double(f64),
+    Bool(bool),
+    Map(Vec<(Value, Value)>),
+}
+
+/// An error reply (`-ERR ...` or a RESP3 bulk error), carried inside the
+/// returned `io::Error`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedisError(pub String);
+
+impl fmt::Display for RedisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "redis error: {}", self.0)
+    }
+}
+
+impl error::Error for RedisError {}
+
+/// Client for Redis speaking RESP2, or RESP3 after `HELLO 3`.
+pub struct RedisClient {
+    stream: Stream,
+    wbuf: Vec<u8>,
+    rbuf: ReadBuf,
+}
+
+impl RedisClient {
+    /// Connects and, with `resp3`, switches the connection protocol with `HELLO 3`.
+    pub fn connect(addr: &str, resp3: bool) -> io::Result<Self> {
+        let mut client = Self {
+            stream: Stream::connect(addr)?,
+            wbuf: Vec::new(),
+            rbuf: ReadBuf::default(),
+        };
+        if resp3 {
+            client.command(&[b"HELLO", b"3"])?;
+        }
+        Ok(client)
+    }
+
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_timeout(timeout)
+    }
+
+    pub fn get(&mut self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        match self.command(&[b"GET", key.as_bytes()])? {
+            Value::Bulk(v) => Ok(Some(v)),
+            Value::Nil => Ok(None),
+            v => Err(unexpected(v)),
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: &[u8]) -> io::Result<()> {
+        self.command(&[b"SET", key.as_bytes(), value]).map(|_| ())
+    }
+
+    /// Returns the number of keys removed.
+    pub fn del(&mut self, keys: &[&str]) -> io::Result<i64> {
+        let mut args: Vec<&[u8]> = vec![b"DEL"];
+        args.extend(keys.iter().map(|k| k.as_bytes()));
+        match self.command(&args)? {
+            Value::Int(n) => Ok(n),
+            v => Err(unexpected(v)),
+        }
+    }
+
+    /// Returns one entry per key, `None` for misses.
+    pub fn mget(&mut self, keys: &[&str]) -> io::Result<Vec<Option<Vec<u8>>>> {
+        let mut args: Vec<&[u8]> = vec![b"MGET"];
+        args.extend(keys.iter().map(|k| k.as_bytes()));
+        match self.command(&args)? {
+            Value::Array(values) => Ok(values
+                .into_iter()
+                .map(|v| match v {
+                    Value::Bulk(v) => Some(v),
+                    _ => None,
+                })
+                .collect()),
+            v => Err(unexpected(v)),
+        }
+    }
+
+    pub fn mset(&mut self, items: &[(&str, &[u8])]) -> io::Result<()> {
+        let mut args: Vec<&[u8]> = vec![b"MSET"];
+        for (k, v) in items {
+            args.push(k.as_bytes());
+            args.push(v);
+        }
+        self.command(&args).map(|_| ())
+    }
+
+    pub fn incr(&mut self, key: &str) -> io::Result<i64> {
+        match self.command(&[b"INCR", key.as_bytes()])? {
+            Value::Int(n) => Ok(n),
+            v => Err(unexpected(v)),
+        }
+    }
+
+    pub fn decr(&mut self, key: &str) -> io::Result<i64> {
+        match self.command(&[b"DECR", key.as_bytes()])? {
+            Value::Int(n) => Ok(n),
+            v => Err(unexpected(v)),
+        }
+    }
+
+    /// Returns the `name:value` fields of `INFO`, leaving out section headers.
+    pub fn info(&mut self) -> io::Result<Vec<(String, String)>> {
+        let text = match self.command(&[b"INFO"])? {
+            Value::Bulk(v) => String::from_utf8_lossy(&v).into_owned(),
+            v => return Err(unexpected(v)),
+        };
+        // RESP3 sends it as a verbatim string, prefixed with its format
+        let text = text.strip_prefix("txt:").unwrap_or(&text);
+        Ok(text
+            .lines()
+            .filter(|l| !l.starts_with('#'))
+            .filter_map(|l| l.split_once(':'))
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect())
+    }
+
+    /// Sends `args` as a RESP array of bulk strings and reads the reply.
+    pub fn command(&mut self, args: &[&[u8]]) -> io::Result<Value> {
+        self.wbuf.clear();
+        write!(self.wbuf, "*{}\r\n", args.len())?;
+        for arg in args {
+            write!(self.wbuf, "${}\r\n", arg.len())?;
+            self.wbuf.extend_from_slice(arg);
+            self.wbuf.extend_from_slice(b"\r\n");
+        }
+        self.stream.write_all(&self.wbuf)?;
+        self.stream.flush()?;
+        self.read_value()
+    }
+
+    fn read_value(&mut self) -> io::Result<Value> {
+        let line = self.rbuf.read_line(&mut self.stream)?.to_string();
+        let invalid = || io::Error::new(ErrorKind::InvalidData, format!("invalid reply: {line}"));
+        if line.is_empty() {
+            return Err(invalid());
+        }
+        let (kind, rest) = line.split_at(1);
+        let int = || rest.parse::<i64>().map_err(|_| invalid());
+        // -1 is the RESP2 null, any other negative length is malformed
+        let len = || match int()? {
+            -1 => Ok(None),
+            n => usize::try_from(n).map(Some).map_err(|_| invalid()),
+        };
+        let count = || len()?.ok_or_else(invalid);
+        match kind {
+            "+" => Ok(Value::Simple(rest.to_string())),
+            "-" => Err(io::Error::other(RedisError(rest.to_string()))),
+            ":" => Ok(Value::Int(int()?)),
+            "$" | "=" => match len()? {
+                None => Ok(Value::Nil),
+                Some(n) => self.rbuf.read_value(&mut self.stream, n).map(Value::Bulk),
+            },
+            "!" => {
+                let e = self.rbuf.read_value(&mut self.stream, count()?)?;
+                Err(io::Error::other(RedisError(
+                    String::from_utf8_lossy(&e).into_owned(),
+                )))
+            }
+            "*" | "~" | ">" => match len()? {
+                None => Ok(Value::Nil),
+                Some(n) => (0..n)
+                    .map(|_| self.read_value())
+                    .collect::<io::Result<_>>()
+                    .map(Value::Array),
+            },
+            "%" => (0..count()?)
+                .map(|_| Ok((self.read_value()?, self.read_value()?)))
+                .collect::<io::Result<_>>()
+                .map(Value::Map),
+            "|" => {
+                // attributes annotate the reply that follows them
+                for _ in 0..count()? * 2 {
+                    self.read_value()?;
+                }
+                self.read_value()
+            }
+            "_" => Ok(Value::Nil),
+            "#" => Ok(Value::Bool(rest == "t")),
+            "," => rest.parse().map(Value::Double).map_err(|_| invalid()),
+            "(" => Ok(Value::Simple(rest.to_string())),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+fn unexpected(v: Value) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, format!("unexpected reply: {v:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+
+    /// Connects to a server that answers whatever is sent with `reply`.
+    fn client(reply: &'static [u8]) -> RedisClient {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut s, _) = listener.accept().unwrap();
+            s.write_all(reply).unwrap();
+            io::copy(&mut s, &mut io::sink()).unwrap();
+        });
+        RedisClient::connect(&addr.to_string(), false).unwrap()
+    }
+
+    #[test]
+    fn replies() {
+        let mut c = client(b"$3\r\nabc\r\n$-1\r\n*2\r\n:1\r\n$0\r\n\r\n*-1\r\n");
+        assert_eq!(c.command(&[b"GET"]).unwrap(), Value::Bulk(b"abc".to_vec()));
+        assert_eq!(c.command(&[b"GET"]).unwrap(), Value::Nil);
+        assert_eq!(
+            c.command(&[b"MGET"]).unwrap(),
+            Value::Array(vec![Value::Int(1), Value::Bulk(Vec::new())])
+        );
+        assert_eq!(c.command(&[b"MGET"]).unwrap(), Value::Nil);
+    }
+
+    #[test]
+    fn malformed() {
+        for reply in [&b"$-2\r\n"[..], b"*-5\r\n", b"%-1\r\n", b"$3\r\nabcd\r\n"] {
+            let mut c = client(reply);
+            let e = c.command(&[b"GET"]).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::InvalidData, "{reply:?}");
+        }
+    }
+}
@@ -0,0 +1,359 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+/// Run every cell of a benchmark matrix described by a TOML or YAML file
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Scenario file (.toml, .yaml or .yml)
+    file: PathBuf,
+}
+
+/// Options are named like the long command line options, eg. `client_type` or
+/// `client-type`, with lists for comma separated values and `true` for flags.
+/// Options given on the command line before the scenario command apply to every
+/// cell that does not set them itself, eg. `bench -s 10.0.0.2 scenario matrix.toml`.
+/// The histogram bounds are shared by every cell, so they are only taken from
+/// the command line, eg. `bench --histogram-max 10s scenario matrix.toml`.
+/// Each axis of the matrix lists values of the option it is named after, or
+/// tables of options labelled by their `name`, which may be empty:
+///
+/// ```toml
+/// output = "results"
+///
+/// [base]
+/// requests = 10000
+/// runs = 1
+///
+/// [matrix]
+/// data = [100, 1000, 10000]
+/// client_type = ["rsmem", "memrs", "basic"]
+/// transport = [
+///     { name = "TCP", server = "127.0.0.1" },
+///     { name = "UDS", socket = "/var/run/memcached/memcached.sock" },
+/// ]
+/// ```
+#[derive(Deserialize)]
+struct Scenario {
+    /// Directory of every cell's result files, named after the cell
+    #[serde(default = "default_output")]
+    output: PathBuf,
+    /// Options of every cell
+    #[serde(default)]
+    base: Map<String, Value>,
+    /// Axes in the order they vary, the first slowest
+    #[serde(default)]
+    matrix: Map<String, Value>,
+}
+
+/// Options that are set once for the whole process, from the command line.
+const PROCESS: &[&str] = &["histogram_min", "histogram_max", "histogram_digits"];
+
+/// Options naming each cell's result files after the cell.
+const OUTPUT: &[&str] = &["out", "json", "interval_out"];
+
+fn default_output() -> PathBuf {
+    PathBuf::from("results")
+}
+
+/// One combination of the matrix, as the command line that runs it.
+pub struct Cell {
+    /// The labels of its value on every axis, eg. `100B_UDS_basic`
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, msg)
+}
+
+fn load(path: &Path) -> io::Result<Scenario> {
+    let text = fs::read_to_string(path)?;
+    let yaml = path.extension().is_some_and(|e| e == "yaml" || e == "yml");
+    let scenario = if yaml {
+        serde_yaml::from_str(&text).map_err(|e| e.to_string())
+    } else {
+        toml::from_str(&text).map_err(|e| e.to_string())
+    };
+    scenario.map_err(|e| invalid(format!("{}: {e}", path.display())))
+}
+
+/// Expands the scenario into its cells, with the `global` options of the command
+/// line under each cell's own. Each cell's results go to `output`, unless the
+/// scenario sets `out`, `json` or `interval_out` itself.
+pub fn cells(args: &Args, global: Map<String, Value>) -> io::Result<Vec<Cell>> {
+    let scenario = load(&args.file)?;
+    if let Some(key) = global.keys().find(|k| OUTPUT.contains(&k.as_str())) {
+        return Err(invalid(format!(
+            "--{} is named after each cell, set output or {key} in the scenario",
+            key.replace('_', "-")
+        )));
+    }
+    fs::create_dir_all(&scenario.output)?;
+
+    // the bounds are already set for the whole process
+    let mut base: Map<String, Value> = global
+        .into_iter()
+        .filter(|(k, _)| !PROCESS.contains(&k.as_str()))
+        .collect();
+    base.extend(normalize(scenario.base));
+    let mut cells = vec![(Vec::new(), base)];
+    for (axis, values) in scenario.matrix {
+        let Value::Array(values) = values else {
+            return Err(invalid(format!("matrix axis {axis} is not a list")));
+        };
+        let mut next = Vec::new();
+        for (labels, options) in &cells {
+            for value in &values {
+                let (label, set) = match value {
+                    Value::Object(o) => {
+                        let label = match o.get("name") {
+                            Some(Value::String(name)) => name.clone(),
+                            _ => return Err(invalid(format!("{axis} entry without a name"))),
+                        };
+                        let mut set = o.clone();
+                        set.remove("name");
+                        (label, normalize(set))
+                    }
+                    v => (
+                        scalar(v),
+                        Map::from_iter([(axis.replace('-', "_"), v.clone())]),
+                    ),
+                };
+                let mut labels = labels.clone();
+                labels.push(label.replace(['/', ' '], "-"));
+                let mut options = options.clone();
+                options.extend(set);
+                next.push((labels, options));
+            }
+        }
+        cells = next;
+    }
+
+    cells
+        .into_iter()
+        .map(|(labels, mut options)| {
+            let labels: Vec<_> = labels.into_iter().filter(|l| !l.is_empty()).collect();
+            let name = if labels.is_empty() {
+                "default".to_string()
+            } else {
+                labels.join("_")
+            };
+            let file = |suffix: &str| {
+                let path = scenario.output.join(format!("{name}{suffix}"));
+                Value::String(path.to_string_lossy().into_owned())
+            };
+            options.entry("out").or_insert_with(|| file(""));
+            options.entry("json").or_insert_with(|| file(".json"));
+            if options.contains_key("interval") {
+                options
+                    .entry("interval_out")
+                    .or_insert_with(|| file("_series.csv"));
+            }
+            Ok(Cell {
+                args: arguments(&name, &options)?,
+                name,
+            })
+        })
+        .collect()
+}
+
+/// Accepts keys spelled with dashes, like the command line.
+fn normalize(options: Map<String, Value>) -> Map<String, Value> {
+    options
+        .into_iter()
+        .map(|(k, v)| (k.replace('-', "_"), v))
+        .collect()
+}
+
+fn scalar(v: &Value) -> String {
+    match v {
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+fn arguments(cell: &str, options: &Map<String, Value>) -> io::Result<Vec<String>> {
+    let mut args = vec!["bench".to_string()];
+    for (key, value) in options {
+        let flag = format!("--{}", key.replace('_', "-"));
+        if PROCESS.contains(&key.as_str()) {
+            return Err(invalid(format!(
+                "{cell}: {key} applies to every cell, pass {flag} before the scenario command"
+            )));
+        }
+        match value {
+            Value::Null | Value::Bool(false) => (),
+            Value::Bool(true) => args.push(flag),
+            Value::Array(values) => {
+                args.push(flag);
+                args.push(values.iter().map(scalar).collect::<Vec<_>>().join(","));
+            }
+            Value::Object(_) => {
+                return Err(invalid(format!("{cell}: option {key} is a table")));
+            }
+            v => {
+                args.push(flag);
+                args.push(scalar(v));
+            }
+        }
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Expands a TOML scenario, its results going to the temp dir.
+    fn expand(name: &str, toml: &str, global: Value) -> io::Result<Vec<Cell>> {
+        let dir =
+            std::env::temp_dir().join(format!("bench-scenario-{}-{name}", std::process::id()));
+        let file = dir.with_extension("toml");
+        let output = format!("output = {:?}\n", dir.display().to_string());
+        fs::write(&file, output + toml).unwrap();
+        let Value::Object(global) = global else {
+            panic!("global options are not a table")
+        };
+        let cells = cells(&Args { file: file.clone() }, global);
+        fs::remove_file(file).unwrap();
+        let _ = fs::remove_dir(dir);
+        cells
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn matrix() {
+        let toml = r#"
+            [base]
+            requests = 10
+            [matrix]
+            data = [100, 1000]
+            transport = [
+                { name = "TCP", server = "127.0.0.1" },
+                { name = "UDS", socket = "/tmp/memcached.sock" },
+                { name = "" },
+            ]
+            "#;
+        let cells = expand("matrix", toml, json!({})).unwrap();
+        let names: Vec<_> = cells.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["100_TCP", "100_UDS", "100", "1000_TCP", "1000_UDS", "1000"]
+        );
+        let out = std::env::temp_dir()
+            .join(format!("bench-scenario-{}-matrix", std::process::id()))
+            .join("1000_UDS");
+        let out = out.to_str().unwrap();
+        assert_eq!(
+            cells[4].args,
+            args(&[
+                "bench",
+                "--requests",
+                "10",
+                "--data",
+                "1000",
+                "--socket",
+                "/tmp/memcached.sock",
+                "--out",
+                out,
+                "--json",
+                &format!("{out}.json"),
+            ])
+        );
+
+        let cells = expand("default", "", json!({})).unwrap();
+        assert_eq!(cells.len(), 1);
+        assert_eq!(cells[0].name, "default");
+    }
+
+    #[test]
+    fn global() {
+        let toml = r#"
+            [base]
+            requests = 10
+            [matrix]
+            client-type = ["basic"]
+            "#;
+        let global = json!({
+            "port": ["11500"],
+            "requests": ["5"],
+            "server_stats": true,
+            "histogram_max": ["10s"],
+        });
+        let cells = expand("global", toml, global).unwrap();
+        // the cell's own options win, the process-wide ones are left out
+        assert_eq!(
+            cells[0].args[..8],
+            args(&[
+                "bench",
+                "--port",
+                "11500",
+                "--requests",
+                "10",
+                "--server-stats",
+                "--client-type",
+                "basic"
+            ])
+        );
+
+        let e = expand("global-out", toml, json!({ "json": ["x.json"] }))
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+        assert!(e.to_string().starts_with("--json is named after each cell"));
+    }
+
+    #[test]
+    fn process_options() {
+        let toml = r#"
+            [matrix]
+            data = [100]
+            histogram-max = ["10s", "1m"]
+            "#;
+        let e = expand("process", toml, json!({})).err().unwrap();
+        assert_eq!(
+            e.to_string(),
+            "100_10s: histogram_max applies to every cell, pass --histogram-max before the scenario command"
+        );
+    }
+
+    #[test]
+    fn options() {
+        let options = json!({
+            "resp3": true,
+            "server_stats": false,
+            "rate": null,
+            "percentiles": [50, 99.9],
+            "key_distribution": "ZIPF",
+            "zipf_s": 1.1,
+        });
+        let Value::Object(options) = options else {
+            unreachable!()
+        };
+        assert_eq!(
+            arguments("cell", &options).unwrap(),
+            args(&[
+                "bench",
+                "--resp3",
+                "--percentiles",
+                "50,99.9",
+                "--key-distribution",
+                "ZIPF",
+                "--zipf-s",
+                "1.1"
+            ])
+        );
+
+        let table = Map::from_iter([("server".to_string(), json!({ "host": "a" }))]);
+        let e = arguments("cell", &table).unwrap_err();
+        assert_eq!(e.to_string(), "cell: option server is a table");
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::Number;
+
+use crate::client::Client;
+use crate::redis::RedisClient;
+use crate::task::{addr, ClientType};
+use crate::Config;
+
+/// Used when --timeout is not set, so a server that never answers `stats` does
+/// not hang the benchmark.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Counters shown after each run, with whether they are gauges rather than
+/// running totals: the value after the run matters for gauges, the delta for
+/// totals.
+const MEMCACHED: &[(&str, bool)] = &[
+    ("cmd_get", false),
+    ("cmd_set", false),
+    ("get_hits", false),
+    ("get_misses", false),
+    ("get_expired", false),
+    ("delete_hits", false),
+    ("delete_misses", false),
+    ("incr_hits", false),
+    ("incr_misses", false),
+    ("evictions", false),
+    ("bytes_read", false),
+    ("bytes_written", false),
+    ("total_connections", false),
+    ("curr_connections", true),
+    ("curr_items", true),
+    ("bytes", true),
+    ("slabs:total_malloced", true),
+    ("rusage_user", false),
+    ("rusage_system", false),
+];
+
+const REDIS: &[(&str, bool)] = &[
+    ("total_commands_processed", false),
+    ("keyspace_hits", false),
+    ("keyspace_misses", false),
+    ("expired_keys", false),
+    ("evicted_keys", false),
+    ("total_net_input_bytes", false),
+    ("total_net_output_bytes", false),
+    ("total_connections_received", false),
+    ("connected_clients", true),
+    ("used_memory", true),
+    ("used_cpu_user", false),
+    ("used_cpu_sys", false),
+];
+
+/// The server's statistics at one point in time, by name.
+pub type Snapshot = BTreeMap<String, String>;
+
+/// Reads the statistics of the server under test over a connection of its own:
+/// memcached `stats` and `stats slabs`, the latter prefixed with `slabs:`, or
+/// Redis `INFO`.
+pub fn capture(c: &Config) -> io::Result<Snapshot> {
+    let addr = addr(c);
+    let timeout = Some(c.timeout.unwrap_or(TIMEOUT));
+    if matches!(c.client_type, ClientType::REDIS) {
+        let mut client = RedisClient::connect(&addr, false)?;
+        client.set_timeout(timeout)?;
+        return Ok(client.info()?.into_iter().collect());
+    }
+    let mut client = Client::connect(&addr)?;
+    client.set_timeout(timeout)?;
+    let mut snapshot: Snapshot = client.stats(None)?.into_iter().collect();
+    for (name, value) in client.stats(Some("slabs"))? {
+        snapshot.insert(format!("slabs:{name}"), value);
+    }
+    Ok(snapshot)
+}
+
+/// A numeric statistic before and after a run.
+#[derive(Clone, Serialize)]
+pub struct Change {
+    pub before: Number,
+    pub after: Number,
+    pub delta: Number,
+}
+
+/// How the server's statistics moved over a run.
+#[derive(Clone, Serialize)]
+pub struct Stats {
+    #[serde(skip)]
+    redis: bool,
+    #[serde(flatten)]
+    changes: BTreeMap<String, Change>,
+}
+
+/// Compares the numeric statistics of two snapshots. Statistics that only
+/// appear after, like a slab class first used during the run, start from 0.
+pub fn diff(c: &Config, before: &Snapshot, after: &Snapshot) -> Stats {
+    let changes = after
+        .iter()
+        .filter_map(|(name, a)| {
+            let a = parse(a)?;
+            let b = match before.get(name) {
+                Some(b) => parse(b)?,
+                None => Number::from(0),
+            };
+            let delta = match (a.as_i64(), b.as_i64()) {
+                (Some(a), Some(b)) => Number::from(a - b),
+                // rusage and cpu times have microsecond precision
+                _ => Number::from_f64(((a.as_f64()? - b.as_f64()?) * 1e6).round() / 1e6)?,
+            };
+            Some((
+                name.clone(),
+                Change {
+                    before: b,
+                    after: a,
+                    delta,
+                },
+            ))
+        })
+        .collect();
+    Stats {
+        redis: matches!(c.client_type, ClientType::REDIS),
+        changes,
+    }
+}
+
+fn parse(value: &str) -> Option<Number> {
+    match value.parse::<i64>() {
+        Ok(n) => Some(Number::from(n)),
+        Err(_) => value.parse().ok().and_then(Number::from_f64),
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let shown = if self.redis { REDIS } else { MEMCACHED };
+        let fields: Vec<_> = shown
+            .iter()
+            .filter_map(|(name, gauge)| {
+                let c = self.changes.get(*name)?;
+                Some(if *gauge {
+                    format!("{name} {} ({})", c.after, signed(&c.delta))
+                } else {
+                    format!("{name} {}", signed(&c.delta))
+                })
+            })
+            .collect();
+        write!(f, " server {}", fields.join(", "))
+    }
+}
+
+fn signed(n: &Number) -> String {
+    match n.as_f64() {
+        Some(v) if v >= 0.0 => format!("+{n}"),
+        _ => n.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+    use clap::Parser;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn config(port: u16, client_type: &str) -> Config {
+        let port = port.to_string();
+        Config::try_parse_from(["bench", "-p", &port, "-t", client_type]).unwrap()
+    }
+
+    fn snapshot(stats: &[(&str, &str)]) -> Snapshot {
+        stats
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn changes() {
+        let before = snapshot(&[
+            ("cmd_get", "100"),
+            ("curr_items", "10"),
+            ("rusage_user", "1.500000"),
+            ("version", "1.6.21"),
+            ("gone", "5"),
+        ]);
+        let after = snapshot(&[
+            ("cmd_get", "350"),
+            ("curr_items", "8"),
+            ("rusage_user", "1.750001"),
+            ("version", "1.6.21"),
+            ("slabs:total_malloced", "2048"),
+        ]);
+        let stats = diff(&config(11211, "basic"), &before, &after);
+        let change = |name: &str| {
+            let c = &stats.changes[name];
+            (
+                c.before.to_string(),
+                c.after.to_string(),
+                c.delta.to_string(),
+            )
+        };
+        assert_eq!(
+            change("cmd_get"),
+            ("100".into(), "350".into(), "250".into())
+        );
+        assert_eq!(change("curr_items"), ("10".into(), "8".into(), "-2".into()));
+        assert_eq!(
+            change("rusage_user"),
+            ("1.5".into(), "1.750001".into(), "0.250001".into())
+        );
+        // appeared during the run
+        assert_eq!(
+            change("slabs:total_malloced"),
+            ("0".into(), "2048".into(), "2048".into())
+        );
+        // not numeric, or gone after the run
+        assert!(!stats.changes.contains_key("version"));
+        assert!(!stats.changes.contains_key("gone"));
+        // totals show their delta, gauges their value after the run as well
+        assert_eq!(
+            stats.to_string(),
+            " server cmd_get +250, curr_items 8 (-2), slabs:total_malloced 2048 (+2048), rusage_user +0.250001"
+        );
+    }
+
+    #[test]
+    fn memcached() {
+        let addr = mock::Server::new().listen_tcp("127.0.0.1:0").unwrap();
+        let c = config(addr.port(), "basic");
+        let before = capture(&c).unwrap();
+        let mut client = Client::connect(&addr.to_string()).unwrap();
+        client.set("k", b"value", 0, 0).unwrap();
+        client.get("k").unwrap();
+        let after = capture(&c).unwrap();
+        assert_eq!(before["curr_items"], "0");
+        assert_eq!(after["curr_items"], "1");
+        assert!(after.contains_key("slabs:total_malloced"));
+        let stats = diff(&c, &before, &after);
+        assert_eq!(stats.changes["cmd_get"].delta, Number::from(1));
+        assert_eq!(stats.changes["cmd_set"].delta, Number::from(1));
+    }
+
+    #[test]
+    fn redis() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let info = "# Stats\r\ntotal_commands_processed:42\r\nkeyspace_hits:7\r\n\r\n# CPU\r\nused_cpu_user:0.25\r\n";
+            let (mut s, _) = listener.accept().unwrap();
+            write!(s, "${}\r\n{info}\r\n", info.len()).unwrap();
+            io::copy(&mut s, &mut io::sink()).unwrap();
+        });
+        let c = config(port, "redis");
+        let info = capture(&c).unwrap();
+        assert_eq!(
+            info,
+            snapshot(&[
+                ("total_commands_processed", "42"),
+                ("keyspace_hits", "7"),
+                ("used_cpu_user", "0.25"),
+            ])
+        );
+        assert_eq!(
+            diff(&c, &info, &info).to_string(),
+            " server total_commands_processed +0, keyspace_hits +0, used_cpu_user +0.0"
+        );
+    }
+}
@@ -0,0 +1,430 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use rand::rngs::SmallRng;
+use rand::Rng;
+use serde::Serialize;
+
+use crate::bench::Result;
+use crate::hdr::HDR;
+
+/// Two-sided 95% Student's t critical values for 1 to 30 degrees of freedom.
+const T95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
+];
+
+/// Modified z-score (Iglewicz and Hoaglin) above which a run is an outlier.
+const OUTLIER_SCORE: f64 = 3.5;
+
+/// Spread of one metric across runs.
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    pub mean: f64,
+    pub median: f64,
+    pub stddev: f64,
+    /// Coefficient of variation, stddev relative to the mean
+    pub cv: f64,
+    /// 95% confidence interval of the mean
+    pub ci95: (f64, f64),
+    /// Runs whose value is far from the others, by the median absolute deviation
+    pub outliers: Vec<usize>,
+}
+
+impl Summary {
+    /// `samples` pairs each value with the run it came from; there must be at least one.
+    pub fn new(samples: &[(usize, f64)]) -> Self {
+        let n = samples.len() as f64;
+        let values: Vec<_> = samples.iter().map(|(_, v)| *v).collect();
+        let mean = values.iter().sum::<f64>() / n;
+        let median = median_of(values.clone());
+        let stddev = if samples.len() > 1 {
+            (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt()
+        } else {
+            0.0
+        };
+        let t = T95
+            .get(samples.len().saturating_sub(2))
+            .copied()
+            .unwrap_or(1.96);
+        let margin = t * stddev / n.sqrt();
+
+        // too few runs to call any of them unusual
+        let mad = median_of(values.iter().map(|v| (v - median).abs()).collect());
+        let outliers = if samples.len() < 3 || mad == 0.0 {
+            Vec::new()
+        } else {
+            samples
+                .iter()
+                .filter(|(_, v)| 0.6745 * (v - median).abs() / mad > OUTLIER_SCORE)
+                .map(|(run, _)| *run)
+                .collect()
+        };
+
+        Summary {
+            mean,
+            median,
+            stddev,
+            cv: if mean == 0.0 { 0.0 } else { stddev / mean },
+            ci95: (mean - margin, mean + margin),
+            outliers,
+        }
+    }
+}
+
+fn median_of(mut values: Vec<f64>) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// Two-sided p-value of the Mann-Whitney U test that `a` and `b` come from the
+/// same distribution. Exact for small samples without ties, otherwise from the
+/// tie-corrected normal approximation.
+pub fn mann_whitney(a: &[f64], b: &[f64]) -> f64 {
+    let (n1, n2) = (a.len(), b.len());
+    if n1 == 0 || n2 == 0 {
+        return 1.0;
+    }
+    // midranks of the pooled samples
+    let mut pooled: Vec<_> = a
+        .iter()
+        .map(|v| (*v, true))
+        .chain(b.iter().map(|v| (*v, false)))
+        .collect();
+    pooled.sort_by(|x, y| x.0.total_cmp(&y.0));
+    let (mut rank_sum, mut ties, mut i) = (0.0, 0.0, 0);
+    while i < pooled.len() {
+        let j = i + pooled[i..]
+            .iter()
+            .take_while(|v| v.0 == pooled[i].0)
+            .count();
+        let rank = (i + j + 1) as f64 / 2.0;
+        rank_sum += rank * pooled[i..j].iter().filter(|v| v.1).count() as f64;
+        let t = (j - i) as f64;
+        ties += t * t * t - t;
+        i = j;
+    }
+    let u = rank_sum - (n1 * (n1 + 1)) as f64 / 2.0;
+
+    if ties == 0.0 && n1 * n2 <= 900 {
+        let counts = u_distribution(n1, n2);
+        let total: f64 = counts.iter().sum();
+        let u = u as usize;
+        let below: f64 = counts[..=u].iter().sum();
+        let above: f64 = counts[u..].iter().sum();
+        return (2.0 * below.min(above) / total).min(1.0);
+    }
+
+    let (n1, n2) = (n1 as f64, n2 as f64);
+    let n = n1 + n2;
+    let sigma = (n1 * n2 / 12.0 * ((n + 1.0) - ties / (n * (n - 1.0)))).sqrt();
+    if sigma == 0.0 {
+        return 1.0;
+    }
+    let z = ((u - n1 * n2 / 2.0).abs() - 0.5).max(0.0) / sigma;
+    (2.0 * (1.0 - normal_cdf(z))).min(1.0)
+}
+
+/// Number of orderings of `n1` and `n2` distinct values giving each U statistic.
+fn u_distribution(n1: usize, n2: usize) -> Vec<f64> {
+    // counts[i][j][u] for samples of i and j values, built up one value at a time
+    let mut counts = vec![vec![Vec::new(); n2 + 1]; n1 + 1];
+    for i in 0..=n1 {
+        for j in 0..=n2 {
+            counts[i][j] = if i == 0 || j == 0 {
+                vec![1.0]
+            } else {
+                // the largest value is either from the first sample, beating all j
+                // of the second, or from the second
+                let mut c = vec![0.0; i * j + 1];
+                for (u, n) in counts[i - 1][j].iter().enumerate() {
+                    c[u + j] += n;
+                }
+                for (u, n) in counts[i][j - 1].iter().enumerate() {
+                    c[u] += n;
+                }
+                c
+            };
+        }
+    }
+    counts.swap_remove(n1).swap_remove(n2)
+}
+
+fn normal_cdf(z: f64) -> f64 {
+    // Abramowitz and Stegun 7.1.26, accurate to 1.5e-7
+    let x = z.abs() / std::f64::consts::SQRT_2;
+    let t = 1.0 / (1.0 + 0.3275911 * x);
+    let poly = t
+        * (0.254829592
+            + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    let erf = 1.0 - poly * (-x * x).exp();
+    if z >= 0.0 {
+        (1.0 + erf) / 2.0
+    } else {
+        (1.0 - erf) / 2.0
+    }
+}
+
+/// Values drawn from each histogram per bootstrap resample, so huge runs stay fast.
+const RESAMPLE_MAX: u64 = 10_000;
+const RESAMPLES: usize = 1000;
+
+/// Bootstrap confidence intervals at level `1 - alpha` of each percentile in `ps`
+/// of `b` minus that of `a`, in the histograms' unit.
+pub fn bootstrap(a: &HDR, b: &HDR, ps: &[f64], alpha: f64, rng: &mut SmallRng) -> Vec<(f64, f64)> {
+    let (a, b) = (Resampler::new(a), Resampler::new(b));
+    let mut deltas = vec![Vec::with_capacity(RESAMPLES); ps.len()];
+    for _ in 0..RESAMPLES {
+        let (a, b) = (a.percentiles(ps, rng), b.percentiles(ps, rng));
+        for (i, d) in deltas.iter_mut().enumerate() {
+            d.push(b[i] - a[i]);
+        }
+    }
+    deltas
+        .into_iter()
+        .map(|mut d| {
+            d.sort_by(f64::total_cmp);
+            let at = |q: f64| d[((q * RESAMPLES as f64) as usize).min(RESAMPLES - 1)];
+            (at(alpha / 2.0), at(1.0 - alpha / 2.0))
+        })
+        .collect()
+}
+
+/// Draws samples from a histogram's recorded values with replacement.
+struct Resampler {
+    values: Vec<u64>,
+    /// Running count up to and including each value
+    cumulative: Vec<u64>,
+}
+
+impl Resampler {
+    fn new(h: &HDR) -> Self {
+        let mut total = 0;
+        let (values, cumulative) = h
+            .recorded()
+            .into_iter()
+            .map(|(v, n)| {
+                total += n;
+                (v, total)
+            })
+            .unzip();
+        Resampler { values, cumulative }
+    }
+
+    /// Percentiles `ps` of one resample.
+    fn percentiles(&self, ps: &[f64], rng: &mut SmallRng) -> Vec<f64> {
+        let Some(&total) = self.cumulative.last() else {
+            return vec![0.0; ps.len()];
+        };
+        let n = total.min(RESAMPLE_MAX);
+        let mut counts = vec![0; self.values.len()];
+        for _ in 0..n {
+            let i = rng.gen_range(0..total);
+            counts[self.cumulative.partition_point(|c| *c <= i)] += 1;
+        }
+        ps.iter()
+            .map(|p| {
+                let rank = ((p / 100.0 * n as f64).ceil() as u64).clamp(1, n);
+                let mut seen = 0;
+                let i = counts
+                    .iter()
+                    .position(|c| {
+                        seen += c;
+                        seen >= rank
+                    })
+                    .unwrap_or(counts.len() - 1);
+                self.values[i] as f64
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "mean {:.2}; median {:.2}; stddev {:.2}; cv {:.2}%; 95% ci [{:.2}, {:.2}]",
+            self.mean,
+            self.median,
+            self.stddev,
+            self.cv * 100.0,
+            self.ci95.0,
+            self.ci95.1
+        )?;
+        if !self.outliers.is_empty() {
+            write!(f, "; outlier runs {:?}", self.outliers)?;
+        }
+        Ok(())
+    }
+}
+
+/// One op across every run that recorded it.
+#[derive(Debug, Clone, Serialize)]
+pub struct OpSummary {
+    pub runs: usize,
+    pub ops_per_sec: Summary,
+    /// Latency percentiles in microseconds, keyed like `p99.9`
+    pub latency_us: BTreeMap<String, Summary>,
+    /// All runs' latencies merged
+    #[serde(skip)]
+    pub histogram: HDR,
+    #[serde(skip)]
+    pub corrected: HDR,
+}
+
+impl OpSummary {
+    /// Summarizes each op of `runs` at `percentiles`, skipping runs in which it
+    /// never succeeded.
+    pub fn of(
+        runs: &[BTreeMap<String, Result>],
+        percentiles: &[f64],
+    ) -> BTreeMap<String, OpSummary> {
+        let mut by_op = BTreeMap::<&String, Vec<(usize, &Result)>>::new();
+        for (i, run) in runs.iter().enumerate() {
+            for (op, r) in run.iter().filter(|(_, r)| r.ops > 0) {
+                by_op.entry(op).or_default().push((i, r));
+            }
+        }
+        by_op
+            .into_iter()
+            .map(|(op, rs)| {
+                let metric = |f: &dyn Fn(&Result) -> f64| {
+                    Summary::new(&rs.iter().map(|(i, r)| (*i, f(r))).collect::<Vec<_>>())
+                };
+                let mut s = OpSummary {
+                    runs: rs.len(),
+                    ops_per_sec: metric(&|r| r.wall_opsps),
+                    latency_us: percentiles
+                        .iter()
+                        .map(|p| {
+                            let s = metric(&|r| r.histogram.percentile(*p) as f64 / 1000.0);
+                            (format!("p{p}"), s)
+                        })
+                        .collect(),
+                    histogram: HDR::new(),
+                    corrected: HDR::new(),
+                };
+                for (_, r) in &rs {
+                    s.histogram += &r.histogram;
+                    s.corrected += &r.corrected;
+                }
+                (op.clone(), s)
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for OpSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, " runs {}\n ops/sec: {}", self.runs, self.ops_per_sec)?;
+        for (p, s) in &self.latency_us {
+            write!(f, "\n {p} µs: {s}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    fn runs(values: &[f64]) -> Vec<(usize, f64)> {
+        values.iter().copied().enumerate().collect()
+    }
+
+    #[test]
+    fn summary() {
+        let s = Summary::new(&runs(&[1.0, 2.0, 3.0, 4.0, 5.0]));
+        assert_eq!((s.mean, s.median), (3.0, 3.0));
+        assert!(close(s.stddev, 2.5f64.sqrt()));
+        // t(4) = 2.776
+        assert!(close(
+            s.ci95.1 - s.mean,
+            2.776 * 2.5f64.sqrt() / 5f64.sqrt()
+        ));
+        assert!(s.outliers.is_empty());
+    }
+
+    #[test]
+    fn outliers() {
+        let s = Summary::new(&runs(&[10.0, 10.1, 9.9, 10.0, 50.0]));
+        assert_eq!(s.outliers, [4]);
+        // keeps the run numbers it was given
+        let s = Summary::new(&[(3, 10.0), (5, 10.1), (7, 9.9), (8, 1.0)]);
+        assert_eq!(s.outliers, [8]);
+        // too few runs, or no spread among the others
+        assert!(Summary::new(&runs(&[10.0, 50.0])).outliers.is_empty());
+        assert!(Summary::new(&runs(&[10.0, 10.0, 10.0, 50.0]))
+            .outliers
+            .is_empty());
+    }
+
+    #[test]
+    fn mann_whitney_exact() {
+        let p = |a: &[f64], b: &[f64]| mann_whitney(a, b);
+        // 2 of the C(6, 3) orderings are as extreme
+        assert!(close(p(&[1.0, 2.0, 3.0], &[4.0, 5.0, 6.0]), 0.1));
+        assert!(close(p(&[6.0, 5.0, 4.0], &[1.0, 2.0, 3.0]), 0.1));
+        assert!(close(
+            p(&[1.0, 2.0, 3.0, 4.0, 5.0], &[6.0, 7.0, 8.0, 9.0, 10.0]),
+            2.0 / 252.0
+        ));
+        // U = 3 of 9, 14 of the 20 orderings are at least as extreme
+        assert!(close(p(&[1.0, 3.0, 5.0], &[2.0, 4.0, 6.0]), 0.7));
+        assert_eq!(p(&[1.0], &[]), 1.0);
+    }
+
+    #[test]
+    fn mann_whitney_normal() {
+        // ties: U = 5.5, tie-corrected z with continuity correction
+        let p = mann_whitney(&[1.0, 2.0, 2.0, 3.0, 4.0], &[2.0, 3.0, 4.0, 4.0, 5.0, 6.0]);
+        assert!(close(p, 0.093377), "{p}");
+        // too many orderings to count: 40 * 40 > 900
+        let a: Vec<_> = (0..40).map(f64::from).collect();
+        let b: Vec<_> = a.iter().map(|v| v + 10.5).collect();
+        let p = mann_whitney(&a, &b);
+        assert!(close(p, 0.000453), "{p}");
+        assert_eq!(mann_whitney(&[1.0, 1.0], &[1.0, 1.0]), 1.0);
+    }
+
+    fn histogram(values: impl IntoIterator<Item = u64>) -> HDR {
+        let mut h = HDR::new();
+        for v in values {
+            h += v;
+        }
+        h
+    }
+
+    #[test]
+    fn bootstrap_ci() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let ps = [50.0, 99.0];
+
+        // every resample of a constant histogram is the same
+        let (a, b) = (histogram([1000; 100]), histogram([2000; 100]));
+        let cis = bootstrap(&a, &b, &ps, 0.05, &mut rng);
+        assert_eq!(cis, [(1000.0, 1000.0), (1000.0, 1000.0)]);
+
+        // the same distribution shifted by 500
+        let a = histogram(1000..2000);
+        let b = histogram(1500..2500);
+        for (lo, hi) in bootstrap(&a, &b, &ps, 0.05, &mut rng) {
+            assert!(lo > 0.0 && lo <= 500.0 && hi >= 500.0, "[{lo}, {hi}]");
+        }
+        for (lo, hi) in bootstrap(&a, &a, &ps, 0.05, &mut rng) {
+            assert!(lo <= 0.0 && hi >= 0.0, "[{lo}, {hi}]");
+        }
+    }
+}
@@ -1,214 +1,339 @@
 use std::collections::BTreeMap;
-use std::ops::Deref;
-use std::rc::Rc;
+use std::future::Future;
+use std::io;
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::Arc;
 
 use rand::rngs::SmallRng;
 use rand::{Rng, SeedableRng};
+use serde::Serialize;
 use std::time::{Duration, Instant};
 
-use memcached;
+use memcached::proto::binary::Status as BinaryStatus;
 use memcached::proto::{MultiOperation, Operation, ProtoType};
 
-use memcache;
-
-use client::Client;
+use crate::client::{Client, ServerError, Store};
+use crate::data::SizeGen;
+use crate::keys::KeyGen;
+use crate::meta::{self, MetaClient};
+use crate::ops::{Op, OpMix};
+use crate::redis::{RedisClient, RedisError};
+use crate::tokio_client::TokioClient;
+#[cfg(feature = "uring")]
+use crate::uring::UringClient;
 
 use crate::*;
 
+/// Op name, latency and payload bytes moved by a single request.
+#[derive(Debug)]
+pub struct TaskResult(pub String, pub Duration, pub usize);
+
+/// Op name and cause of a failed request.
 #[derive(Debug)]
-pub struct TaskResult(pub String, pub Duration);
+pub struct TaskError(pub String, pub ErrorClass);
+
+pub type TaskOutcome = std::result::Result<TaskResult, TaskError>;
+
+/// Why a request failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorClass {
+    /// No response within `--timeout`
+    Timeout,
+    /// The key, or the item a conditional command depends on, does not exist
+    Miss,
+    /// An error reply or a response that could not be parsed
+    Protocol,
+    /// The server closed or reset the connection
+    Reset,
+    Other,
+}
+
+impl ErrorClass {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorClass::Timeout => "timeout",
+            ErrorClass::Miss => "miss",
+            ErrorClass::Protocol => "protocol",
+            ErrorClass::Reset => "reset",
+            ErrorClass::Other => "other",
+        }
+    }
+}
+
+impl From<&io::Error> for ErrorClass {
+    fn from(e: &io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => ErrorClass::Timeout,
+            io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::UnexpectedEof => ErrorClass::Reset,
+            io::ErrorKind::InvalidData => ErrorClass::Protocol,
+            _ if e
+                .get_ref()
+                .is_some_and(|e| e.is::<ServerError>() || e.is::<RedisError>()) =>
+            {
+                ErrorClass::Protocol
+            }
+            _ => ErrorClass::Other,
+        }
+    }
+}
+
+impl From<&memcached::proto::Error> for ErrorClass {
+    fn from(e: &memcached::proto::Error) -> Self {
+        match e {
+            memcached::proto::Error::IoError(e) => e.into(),
+            memcached::proto::Error::BinaryProtoError(e)
+                if e.status() == BinaryStatus::KeyNotFound =>
+            {
+                ErrorClass::Miss
+            }
+            memcached::proto::Error::BinaryProtoError(_) => ErrorClass::Protocol,
+            memcached::proto::Error::OtherError { .. } => ErrorClass::Other,
+        }
+    }
+}
+
+impl From<&memcache::MemcacheError> for ErrorClass {
+    fn from(e: &memcache::MemcacheError) -> Self {
+        match e {
+            memcache::MemcacheError::IOError(e) => e.into(),
+            memcache::MemcacheError::CommandError(memcache::CommandError::KeyNotFound) => {
+                ErrorClass::Miss
+            }
+            memcache::MemcacheError::ClientError(_)
+            | memcache::MemcacheError::ServerError(_)
+            | memcache::MemcacheError::CommandError(_)
+            | memcache::MemcacheError::ParseError(_) => ErrorClass::Protocol,
+            _ => ErrorClass::Other,
+        }
+    }
+}
+
+/// Turns the result of a timed request, `None` for a miss, into its outcome.
+fn outcome(op: &str, start: Instant, r: io::Result<Option<usize>>) -> TaskOutcome {
+    match r {
+        Ok(Some(bytes)) => Ok(TaskResult(op.into(), start.elapsed(), bytes)),
+        Ok(None) => Err(TaskError(op.into(), ErrorClass::Miss)),
+        Err(e) => Err(TaskError(op.into(), (&e).into())),
+    }
+}
+
+/// Whether the connection is left in an unknown state, eg. mid-response, and
+/// has to be replaced before it can be used again.
+fn broken(r: &TaskOutcome) -> bool {
+    matches!(r, Err(TaskError(_, class)) if *class != ErrorClass::Miss)
+}
 
 pub trait Task {
     fn init(&mut self);
-    fn run(&mut self) -> TaskResult;
+    fn run(&mut self) -> TaskOutcome;
+    /// Runs `--pipeline` requests back to back, for clients that can pipeline.
+    fn run_batch(&mut self) -> Vec<TaskOutcome> {
+        vec![self.run()]
+    }
+}
+
+pub type LocalBoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+/// Async counterpart to `Task`, driven on a per-thread runtime by the async engines.
+pub trait AsyncTask {
+    fn init(&mut self) -> LocalBoxFuture<'_, ()>;
+    fn run(&mut self) -> LocalBoxFuture<'_, TaskOutcome>;
 }
 
 #[allow(dead_code)]
-#[derive(clap::ValueEnum, Debug, Clone)]
+#[derive(clap::ValueEnum, Debug, Clone, Serialize)]
 pub enum ClientType {
     MEMRS,
     RSMEM,
     BASIC,
+    META,
+    REDIS,
+    TOKIO,
+    #[cfg(feature = "uring")]
+    URING,
+}
+
+impl ClientType {
+    /// Whether the client can run `op` from an `--ops` mix. Clients without a
+    /// mix only follow `--ratio`.
+    pub fn supports(&self, op: Op) -> bool {
+        match self {
+            ClientType::BASIC | ClientType::META => op != Op::MSET,
+            ClientType::REDIS => matches!(
+                op,
+                Op::SET | Op::GET | Op::DELETE | Op::INCR | Op::DECR | Op::MGET | Op::MSET
+            ),
+            _ => false,
+        }
+    }
+}
+
+/// Connects a task, failing if the server cannot be reached.
+pub fn task_factory(c: Arc<Config>) -> io::Result<Box<dyn Task>> {
+    Ok(match &c.client_type {
+        ClientType::MEMRS => Box::new(MemRS::new(c)?),
+        ClientType::RSMEM => Box::new(RSMem::new(c)?),
+        ClientType::BASIC => Box::new(Basic::new(c)?),
+        ClientType::META => Box::new(Meta::new(c)?),
+        ClientType::REDIS => Box::new(Redis::new(c)?),
+        t => panic!("{t:?} is an async client, use an async engine"),
+    })
 }
 
-pub fn task_factory(c: Rc<Config>) -> Box<dyn Task> {
-    match &c.client_type {
-        ClientType::MEMRS => return Box::new(MemRS::new(c)),
-        ClientType::RSMEM => return Box::new(RSMem::new(c)),
-        ClientType::BASIC => return Box::new(Basic::new(c)),
+pub async fn async_task_factory(c: Arc<Config>) -> io::Result<Box<dyn AsyncTask>> {
+    Ok(match &c.client_type {
+        ClientType::TOKIO => Box::new(Tokio::new(c).await?),
+        #[cfg(feature = "uring")]
+        ClientType::URING => Box::new(Uring::new(c).await?),
+        t => panic!("{t:?} is a blocking client, use --engine thread"),
+    })
+}
+
+/// Server address for the hand-written clients: the UNIX socket if set,
+/// `server:port` otherwise.
+pub(crate) fn addr(c: &Config) -> String {
+    match &c.socket {
+        Some(sock) => sock.clone(),
+        None => format!("{}:{}", c.server, c.port),
     }
 }
 
 struct MemRS {
-    config: Rc<Config>,
+    config: Arc<Config>,
     client: memcached::client::Client,
+    keys: KeyGen,
+    sizes: SizeGen,
     rng: SmallRng,
 }
 
 impl MemRS {
-    fn new(c: Rc<Config>) -> Self {
+    fn new(c: Arc<Config>) -> io::Result<Self> {
         dbg!("MEMRS");
+        Ok(MemRS {
+            client: Self::connect(&c)?,
+            keys: KeyGen::new(&c).expect("key distribution checked at startup"),
+            sizes: SizeGen::new(&c.data).expect("data distribution checked at startup"),
+            config: c,
+            rng: SmallRng::from_entropy(),
+        })
+    }
+
+    fn connect(c: &Config) -> io::Result<memcached::client::Client> {
         let mut addr = format!("tcp://{}:{}", c.server, c.port);
         if let Some(sock) = &c.socket {
             addr = format!("unix://{}", sock)
         }
-        MemRS {
-            config: c,
-            client: memcached::Client::connect(&[(addr, 1)], ProtoType::Binary).unwrap(),
-            rng: SmallRng::from_entropy(),
+        memcached::Client::connect(&[(addr, 1)], ProtoType::Binary)
+    }
+
+    /// Stores the payload under `key`, or when chunking, the chunk count under
+    /// `key` and the chunks under `key.N`.
+    fn store(
+        client: &mut memcached::client::Client,
+        c: &Config,
+        key: &str,
+        value: &[u8],
+    ) -> memcached::proto::MemCachedResult<()> {
+        if let Some(chunk_size) = c.chunk_size {
+            let chunks = value.chunks(chunk_size as usize);
+            client.set(key.as_bytes(), &(chunks.len() as u8).to_be_bytes(), 0, 0)?;
+            let keys: Vec<_> = (0..chunks.len())
+                .map(|i| format!("{}.{}", key, i).into_boxed_str().into_boxed_bytes())
+                .collect();
+            let kv: BTreeMap<&[u8], (&[u8], u32, u32)> = keys
+                .iter()
+                .map(|k| k.as_ref())
+                .zip(chunks.map(|v| (v, 0, 0)))
+                .collect();
+            client.set_multi(kv)
+        } else {
+            client.set(key.as_bytes(), value, 0, 0)
+        }
+    }
+
+    /// Reads back what `store` wrote, merging chunks into a single value.
+    fn fetch(
+        client: &mut memcached::client::Client,
+        c: &Config,
+        key: &str,
+    ) -> memcached::proto::MemCachedResult<Vec<u8>> {
+        if c.chunk_size.is_some() {
+            let v = client.get(key.as_bytes())?;
+            let chunk_count = u8::from_be_bytes([(*v.0)[0]]);
+            let keys: Vec<_> = (0..chunk_count)
+                .map(|i| format!("{}.{}", key, i).into_boxed_str().into_boxed_bytes())
+                .collect();
+            let v = client.get_multi(&keys.iter().map(|k| k.as_ref()).collect::<Vec<_>>())?;
+            Ok(keys
+                .iter()
+                .map(|k| v.get(k.as_ref()))
+                .fold(Vec::new(), |mut acc: Vec<u8>, v| {
+                    if let Some(v) = v {
+                        acc.extend(v.0.iter());
+                    }
+                    acc
+                }))
+        } else {
+            Ok(client.get(key.as_bytes())?.0)
         }
     }
 }
 
 impl Task for MemRS {
     fn init(&mut self) {
-        if self.config.data_bytes.len() != 0 {
-            if let Some(chunk_size) = self.config.chunk_size {
-                let chunks = self.config.data_bytes.chunks(chunk_size as usize);
-                self.client
-                    .set(
-                        self.config.key.as_bytes(),
-                        &(chunks.len() as u8).to_be_bytes(),
-                        0,
-                        0,
-                    )
-                    .unwrap();
-                let keys: Vec<_> = (0..chunks.len())
-                    .map(|i| {
-                        format!("{}.{}", self.config.key, i)
-                            .into_boxed_str()
-                            .into_boxed_bytes()
-                    })
-                    .collect();
-                let kv: BTreeMap<&[u8], (&[u8], u32, u32)> = keys
-                    .iter()
-                    .map(|k| k.as_ref())
-                    .zip(chunks.clone().map(|v| (v, 0, 0)))
-                    .collect();
-                self.client.set_multi(kv).unwrap();
-
-                let v = self.client.get(self.config.key.as_bytes()).unwrap();
-                assert_eq!(u8::from_be_bytes([(*v.0)[0]]), chunks.len() as u8);
-                let v = self
-                    .client
-                    .get_multi(&keys.iter().map(|k| k.as_ref()).collect::<Vec<_>>())
-                    .unwrap();
-                assert_eq!(
-                    keys.iter().map(|k| v.get(k.as_ref())).fold(
-                        Vec::new(),
-                        |mut acc: Vec<u8>, v| {
-                            match v {
-                                Some(v) => {
-                                    acc.extend(v.0.iter());
-                                }
-                                None => (),
-                            }
-                            acc
-                        },
-                    ),
-                    chunks.fold(Vec::new(), |mut acc, v| {
-                        acc.extend(v.iter());
-                        acc
-                    })
-                );
-            } else {
-                self.client
-                    .set(
-                        self.config.key.as_bytes(),
-                        self.config.data_bytes.deref(),
-                        0,
-                        0,
-                    )
-                    .unwrap();
-
-                let v = self.client.get(self.config.key.as_bytes()).unwrap();
-                assert_eq!(v.0, self.config.data_bytes);
+        if !self.config.data_bytes.is_empty() {
+            for key in self.keys.all() {
+                let value = self.sizes.value(&self.config.data_bytes);
+                Self::store(&mut self.client, &self.config, &key, value).unwrap();
+                let v = Self::fetch(&mut self.client, &self.config, &key).unwrap();
+                assert_eq!(v, value);
             }
         }
     }
-    fn run(&mut self) -> TaskResult {
+    fn run(&mut self) -> TaskOutcome {
         let r: f64 = self.rng.gen();
+        let key = self.keys.next();
+        let value = self.sizes.value(&self.config.data_bytes);
         let start = Instant::now();
-        let op = if r < self.config.ratio {
-            if let Some(chunk_size) = self.config.chunk_size {
-                let chunks = self.config.data_bytes.chunks(chunk_size as usize);
-                self.client
-                    .set(
-                        self.config.key.as_bytes(),
-                        &(chunks.len() as u8).to_be_bytes(),
-                        0,
-                        0,
-                    )
-                    .unwrap();
-                let keys: Vec<_> = (0..chunks.len())
-                    .map(|i| {
-                        format!("{}.{}", self.config.key, i)
-                            .into_boxed_str()
-                            .into_boxed_bytes()
-                    })
-                    .collect();
-                let kv: BTreeMap<&[u8], (&[u8], u32, u32)> = keys
-                    .iter()
-                    .map(|k| k.as_ref())
-                    .zip(chunks.map(|v| (v, 0, 0)))
-                    .collect();
-                self.client.set_multi(kv).unwrap();
-            } else {
-                self.client
-                    .set(
-                        self.config.key.as_bytes(),
-                        self.config.data_bytes.deref(),
-                        0,
-                        0,
-                    )
-                    .unwrap();
-            }
-            "SET"
+        let (op, r) = if r < self.config.ratio {
+            let r = Self::store(&mut self.client, &self.config, key, value);
+            ("SET", r.map(|_| value.len()))
         } else {
-            if let Some(_) = self.config.chunk_size {
-                let v = self.client.get(self.config.key.as_bytes()).unwrap();
-                let chunk_count = u8::from_be_bytes([(*v.0)[0]]);
-                let keys: Vec<_> = (0..chunk_count)
-                    .map(|i| {
-                        format!("{}.{}", self.config.key, i)
-                            .into_boxed_str()
-                            .into_boxed_bytes()
-                    })
-                    .collect();
-                let v = self
-                    .client
-                    .get_multi(&keys.iter().map(|k| k.as_ref()).collect::<Vec<_>>())
-                    .unwrap();
-                //merge results even if its not used to test perf properly
-                let _ = keys.iter().map(|k| v.get(k.as_ref())).fold(
-                    Vec::new(),
-                    |mut acc: Vec<u8>, v| {
-                        match v {
-                            Some(v) => {
-                                acc.extend(v.0.iter());
-                            }
-                            None => (),
-                        }
-                        acc
-                    },
-                );
-            } else {
-                self.client.get(self.config.key.as_bytes()).unwrap();
-            }
-            "GET"
+            //merge results even if its not used to test perf properly
+            let r = Self::fetch(&mut self.client, &self.config, key);
+            ("GET", r.map(|v| v.len()))
         };
-        TaskResult(op.into(), start.elapsed())
+        match r {
+            Ok(bytes) => Ok(TaskResult(op.into(), start.elapsed(), bytes)),
+            Err(e) => {
+                let class = ErrorClass::from(&e);
+                if class != ErrorClass::Miss {
+                    if let Ok(client) = Self::connect(&self.config) {
+                        self.client = client;
+                    }
+                }
+                Err(TaskError(op.into(), class))
+            }
+        }
     }
 }
 
 struct RSMem {
-    config: Rc<Config>,
+    config: Arc<Config>,
     client: memcache::Client,
+    keys: KeyGen,
+    sizes: SizeGen,
     rng: SmallRng,
 }
 
 impl RSMem {
-    fn new(c: Rc<Config>) -> Self {
+    fn new(c: Arc<Config>) -> io::Result<Self> {
         dbg!("RSMEM");
         let mut addr = format!("memcache+tcp://{}:{}", c.server, c.port);
         if let Some(port) = c.udp_port {
@@ -221,101 +346,648 @@ impl RSMem {
             "{}?protocol=binary&connect_timeout=1&tcp_nodelay=true",
             addr
         );
-        let client = memcache::connect(addr).unwrap();
+        // the pool replaces broken connections itself
+        let client = memcache::connect(addr).map_err(io::Error::other)?;
+        let timeout = c.timeout.unwrap_or(Duration::from_secs(1));
         client
-            .set_read_timeout(Some(Duration::from_secs(1)))
-            .unwrap();
+            .set_read_timeout(Some(timeout))
+            .map_err(io::Error::other)?;
         client
-            .set_write_timeout(Some(Duration::from_secs(1)))
-            .unwrap();
-        RSMem {
+            .set_write_timeout(Some(timeout))
+            .map_err(io::Error::other)?;
+        Ok(RSMem {
+            keys: KeyGen::new(&c).expect("key distribution checked at startup"),
+            sizes: SizeGen::new(&c.data).expect("data distribution checked at startup"),
             config: c,
-            client: client,
+            client,
             rng: SmallRng::from_entropy(),
-        }
+        })
     }
 }
 
 impl Task for RSMem {
     fn init(&mut self) {
-        if self.config.data_string.len() != 0 {
-            self.client
-                .set(&self.config.key, self.config.data_string.deref(), 0)
-                .unwrap();
+        if !self.config.data_bytes.is_empty() {
+            for key in self.keys.all() {
+                let value = self.sizes.value(&self.config.data_bytes);
+                self.client.set(&key, value, 0).unwrap();
 
-            let v: String = self.client.get(&self.config.key).unwrap().unwrap();
-            assert_eq!(v, self.config.data_string);
+                let v: Vec<u8> = self.client.get(&key).unwrap().unwrap();
+                assert_eq!(v, value);
+            }
         }
     }
-    fn run(&mut self) -> TaskResult {
+    fn run(&mut self) -> TaskOutcome {
         let r: f64 = self.rng.gen();
+        let key = self.keys.next();
+        let value = self.sizes.value(&self.config.data_bytes);
         let start = Instant::now();
-        let op = if r < self.config.ratio {
-            self.client
-                .set(&self.config.key, self.config.data_string.deref(), 0)
-                .unwrap();
-            "SET"
+        let (op, r) = if r < self.config.ratio {
+            let r = self.client.set(key, value, 0);
+            ("SET", r.map(|_| Some(value.len())))
         } else {
-            self.client.get::<Vec<u8>>(&self.config.key).unwrap();
-            "GET"
+            let r = self.client.get::<Vec<u8>>(key);
+            ("GET", r.map(|v| v.map(|v| v.len())))
         };
-        TaskResult(op.into(), start.elapsed())
+        match r {
+            Ok(Some(bytes)) => Ok(TaskResult(op.into(), start.elapsed(), bytes)),
+            Ok(None) => Err(TaskError(op.into(), ErrorClass::Miss)),
+            Err(e) => Err(TaskError(op.into(), (&e).into())),
+        }
     }
 }
 
 struct Basic {
-    config: Rc<Config>,
+    config: Arc<Config>,
     client: client::Client,
+    keys: KeyGen,
+    sizes: SizeGen,
+    ops: OpMix,
     rng: SmallRng,
 }
 
 impl Basic {
-    fn new(c: Rc<Config>) -> Self {
+    fn new(c: Arc<Config>) -> io::Result<Self> {
         dbg!("Basic");
-        let mut addr = format!("{}:{}", c.server, c.port);
-        if let Some(sock) = &c.socket {
-            addr = sock.clone()
-        }
-        Basic {
+        Ok(Basic {
+            client: Self::connect(&c)?,
+            keys: KeyGen::new(&c).expect("key distribution checked at startup"),
+            sizes: SizeGen::new(&c.data).expect("data distribution checked at startup"),
+            ops: c.ops.clone().unwrap_or_else(|| OpMix::ratio(c.ratio)),
             config: c,
-            client: Client::connect(&addr).unwrap(),
             rng: SmallRng::from_entropy(),
+        })
+    }
+
+    fn connect(c: &Config) -> io::Result<Client> {
+        let client = Client::connect(&addr(c))?;
+        client.set_timeout(c.timeout)?;
+        Ok(client)
+    }
+
+    /// Picks the next op and does anything it needs besides the command under
+    /// test, so none of it is timed.
+    fn prepare(&mut self) -> io::Result<Pending> {
+        let op = self.ops.pick(&mut self.rng);
+        let batch = match op {
+            Op::MGET => (0..self.config.mget_keys)
+                .map(|_| self.keys.next().to_owned())
+                .collect(),
+            _ => Vec::new(),
+        };
+        let key = match op {
+            Op::INCR | Op::DECR => counter_key(self.keys.next()),
+            _ => self.keys.next().to_owned(),
+        };
+        let cas = match op {
+            Op::CAS => self.client.gets(&key)?.map_or(0, |v| v.1),
+            _ => 0,
+        };
+        Ok(Pending {
+            op,
+            key,
+            value: self.sizes.place(&self.config.data_bytes),
+            cas,
+            batch,
+        })
+    }
+
+    /// Writes `depth` requests at once and then reads their responses in order,
+    /// so each request's latency runs from the write to its own response. An
+    /// I/O error fails the rest of the batch, whose responses can no longer be
+    /// told apart.
+    fn pipeline(&mut self, depth: usize) -> Vec<TaskOutcome> {
+        let mut pending = Vec::with_capacity(depth);
+        for _ in 0..depth {
+            match self.prepare() {
+                Ok(p) => pending.push(p),
+                Err(e) => {
+                    // only CAS sends a request of its own while preparing
+                    self.reconnect();
+                    return vec![Err(TaskError(Op::CAS.name().into(), (&e).into()))];
+                }
+            }
+        }
+        let client = &mut self.client;
+
+        let start = Instant::now();
+        for p in &pending {
+            let key = p.key.as_str();
+            let value = self.config.data_bytes.get(p.value.clone());
+            match p.op {
+                Op::SET => client.push_store("set", key, value, 0, 0, None),
+                Op::ADD => client.push_store("add", key, value, 0, 0, None),
+                Op::REPLACE => client.push_store("replace", key, value, 0, 0, None),
+                Op::APPEND => client.push_store("append", key, value, 0, 0, None),
+                Op::PREPEND => client.push_store("prepend", key, value, 0, 0, None),
+                Op::CAS => client.push_store("cas", key, value, 0, 0, Some(p.cas)),
+                Op::GET => client.push_line(&format!("get {key}")),
+                Op::GETS => client.push_line(&format!("gets {key}")),
+                Op::GAT => client.push_line(&format!("gat 0 {key}")),
+                Op::MGET => client.push_line(&format!("get {}", p.batch.join(" "))),
+                Op::DELETE => client.push_line(&format!("delete {key}")),
+                Op::INCR => client.push_line(&format!("incr {key} 1")),
+                Op::DECR => client.push_line(&format!("decr {key} 1")),
+                Op::TOUCH => client.push_line(&format!("touch {key} 0")),
+                Op::MSET => unreachable!("MSET is rejected for BASIC at startup"),
+            }
+        }
+        let mut failed = client.send().err().map(|e| ErrorClass::from(&e));
+
+        let results: Vec<_> = pending
+            .iter()
+            .map(|p| {
+                if let Some(class) = failed {
+                    return Err(TaskError(p.op.name().into(), class));
+                }
+                let r = match p.op {
+                    Op::SET | Op::ADD | Op::REPLACE | Op::APPEND | Op::PREPEND | Op::CAS => {
+                        client.read_stored().map(|s| match (p.op, s) {
+                            (Op::REPLACE | Op::APPEND | Op::PREPEND, Store::NotStored)
+                            | (_, Store::NotFound) => None,
+                            _ => Some(p.value.len()),
+                        })
+                    }
+                    Op::GET | Op::GETS | Op::GAT => client
+                        .read_items()
+                        .map(|items| items.first().map(|i| i.value.len())),
+                    Op::MGET => client
+                        .read_items()
+                        .map(|items| Some(items.iter().map(|i| i.value.len()).sum())),
+                    Op::DELETE | Op::TOUCH => client.read_found().map(|f| f.then_some(0)),
+                    Op::INCR | Op::DECR => client.read_number().map(|n| n.map(|_| 0)),
+                    Op::MSET => unreachable!(),
+                };
+                if let Err(e) = &r {
+                    failed = Some(e.into());
+                }
+                outcome(p.op.name(), start, r)
+            })
+            .collect();
+        if failed.is_some() {
+            self.reconnect();
+        }
+        results
+    }
+
+    fn reconnect(&mut self) {
+        if let Ok(client) = Self::connect(&self.config) {
+            self.client = client;
         }
     }
 }
 
+/// A request of a `Basic` pipeline, prepared before the clock starts.
+struct Pending {
+    op: Op,
+    /// The counter key for INCR/DECR
+    key: String,
+    /// Where the value of a write is in the payload
+    value: Range<usize>,
+    cas: u64,
+    /// Keys of an MGET
+    batch: Vec<String>,
+}
+
+/// INCR/DECR need a numeric value, so they use a counter alongside each key.
+fn counter_key(key: &str) -> String {
+    format!("{key}:count")
+}
+
 impl Task for Basic {
     fn init(&mut self) {
-        if self.config.data_string.len() != 0 {
-            self.client
-                .set(
-                    self.config.key.as_str(),
-                    self.config.data_bytes.deref(),
-                    0,
-                    0,
+        if !self.config.data_bytes.is_empty() {
+            for key in self.keys.all() {
+                let value = self.sizes.value(&self.config.data_bytes);
+                self.client.set(&key, value, 0, 0).unwrap();
+                let v: Vec<u8> = self.client.get(&key).unwrap().unwrap();
+                assert!(v == value)
+            }
+        }
+        if self.ops.contains(Op::INCR) || self.ops.contains(Op::DECR) {
+            for key in self.keys.all() {
+                self.client.set(&counter_key(&key), b"0", 0, 0).unwrap();
+            }
+        }
+    }
+    fn run(&mut self) -> TaskOutcome {
+        self.pipeline(1).remove(0)
+    }
+    fn run_batch(&mut self) -> Vec<TaskOutcome> {
+        self.pipeline(self.config.pipeline)
+    }
+}
+
+struct Meta {
+    config: Arc<Config>,
+    client: MetaClient,
+    keys: KeyGen,
+    sizes: SizeGen,
+    ops: OpMix,
+    rng: SmallRng,
+}
+
+impl Meta {
+    fn new(c: Arc<Config>) -> io::Result<Self> {
+        Ok(Meta {
+            client: Self::connect(&c)?,
+            keys: KeyGen::new(&c).expect("key distribution checked at startup"),
+            sizes: SizeGen::new(&c.data).expect("data distribution checked at startup"),
+            ops: c.ops.clone().unwrap_or_else(|| OpMix::ratio(c.ratio)),
+            config: c,
+            rng: SmallRng::from_entropy(),
+        })
+    }
+
+    fn connect(c: &Config) -> io::Result<MetaClient> {
+        let client = MetaClient::connect(&addr(c))?;
+        client.set_timeout(c.timeout)?;
+        Ok(client)
+    }
+
+    /// Sends a single `op`, resetting `start` once everything it needs besides
+    /// the command under test is done.
+    fn request(&mut self, op: Op, start: &mut Instant) -> io::Result<Option<usize>> {
+        let batch: Vec<String> = match op {
+            Op::MGET => (0..self.config.mget_keys)
+                .map(|_| self.keys.next().to_owned())
+                .collect(),
+            _ => Vec::new(),
+        };
+        let batch: Vec<&str> = batch.iter().map(String::as_str).collect();
+        let key = self.keys.next();
+        let counter = counter_key(key);
+        let value = self.sizes.value(&self.config.data_bytes);
+        let get_flags = format!("v {}", self.config.meta_flags);
+        let cas_flags = match op {
+            Op::CAS => {
+                let cas = self.client.mg(key, "c")?.cas.unwrap_or_default();
+                format!("C{cas}")
+            }
+            _ => String::new(),
+        };
+        // the misses of a conditional store, the others fail on an existing item
+        let store = |m: meta::Meta| match (op, m.status) {
+            (Op::REPLACE | Op::APPEND | Op::PREPEND, meta::Status::NotStored)
+            | (_, meta::Status::NotFound) => None,
+            _ => Some(value.len()),
+        };
+        let found = |m: meta::Meta| match m.status {
+            meta::Status::Miss | meta::Status::NotFound => None,
+            _ => Some(m.value.map_or(0, |v| v.len())),
+        };
+
+        *start = Instant::now();
+        match op {
+            Op::SET => self.client.ms(key, value, "").map(store),
+            Op::GET => self.client.mg(key, &get_flags).map(found),
+            Op::ADD => self.client.ms(key, value, "ME").map(store),
+            Op::REPLACE => self.client.ms(key, value, "MR").map(store),
+            Op::APPEND => self.client.ms(key, value, "MA").map(store),
+            Op::PREPEND => self.client.ms(key, value, "MP").map(store),
+            Op::CAS => self.client.ms(key, value, &cas_flags).map(store),
+            Op::GETS => self.client.mg(key, "v c").map(found),
+            Op::DELETE => self.client.md(key, "").map(found),
+            Op::INCR => self.client.ma(&counter, "").map(found),
+            Op::DECR => self.client.ma(&counter, "MD").map(found),
+            Op::TOUCH => self.client.mg(key, "T0").map(found),
+            Op::GAT => self.client.mg(key, "v T0").map(found),
+            Op::MGET => self.client.mg_batch(&batch, &get_flags).map(|hits| {
+                Some(
+                    hits.iter()
+                        .map(|m| m.value.as_ref().map_or(0, |v| v.len()))
+                        .sum(),
                 )
-                .unwrap();
-            let v: Vec<u8> = self.client.get(self.config.key.as_str()).unwrap().unwrap();
-            assert!(v == self.config.data_bytes)
+            }),
+            Op::MSET => unreachable!("MSET is rejected for META at startup"),
         }
     }
-    fn run(&mut self) -> TaskResult {
-        let r: f64 = self.rng.gen();
+}
+
+impl Task for Meta {
+    fn init(&mut self) {
+        if !self.config.data_bytes.is_empty() {
+            for key in self.keys.all() {
+                let value = self.sizes.value(&self.config.data_bytes);
+                self.client.ms(&key, value, "").unwrap();
+                let v = self.client.mg(&key, "v").unwrap();
+                assert!(v.value.as_deref() == Some(value))
+            }
+        }
+        if self.ops.contains(Op::INCR) || self.ops.contains(Op::DECR) {
+            for key in self.keys.all() {
+                self.client.ms(&counter_key(&key), b"0", "").unwrap();
+            }
+        }
+    }
+    fn run(&mut self) -> TaskOutcome {
+        let op = self.ops.pick(&mut self.rng);
+        let mut start = Instant::now();
+        let r = self.request(op, &mut start);
+        let r = outcome(op.name(), start, r);
+        if broken(&r) {
+            if let Ok(client) = Self::connect(&self.config) {
+                self.client = client;
+            }
+        }
+        r
+    }
+}
+
+struct Redis {
+    config: Arc<Config>,
+    client: RedisClient,
+    keys: KeyGen,
+    sizes: SizeGen,
+    ops: OpMix,
+    rng: SmallRng,
+}
+
+impl Redis {
+    fn new(c: Arc<Config>) -> io::Result<Self> {
+        Ok(Redis {
+            client: Self::connect(&c)?,
+            keys: KeyGen::new(&c).expect("key distribution checked at startup"),
+            sizes: SizeGen::new(&c.data).expect("data distribution checked at startup"),
+            ops: c.ops.clone().unwrap_or_else(|| OpMix::ratio(c.ratio)),
+            config: c,
+            rng: SmallRng::from_entropy(),
+        })
+    }
+
+    fn connect(c: &Config) -> io::Result<RedisClient> {
+        let client = RedisClient::connect(&addr(c), c.resp3)?;
+        client.set_timeout(c.timeout)?;
+        Ok(client)
+    }
+}
+
+impl Task for Redis {
+    fn init(&mut self) {
+        if !self.config.data_bytes.is_empty() {
+            for key in self.keys.all() {
+                let value = self.sizes.value(&self.config.data_bytes);
+                self.client.set(&key, value).unwrap();
+                let v = self.client.get(&key).unwrap();
+                assert!(v.as_deref() == Some(value))
+            }
+        }
+        if self.ops.contains(Op::INCR) || self.ops.contains(Op::DECR) {
+            for key in self.keys.all() {
+                self.client.set(&counter_key(&key), b"0").unwrap();
+            }
+        }
+    }
+    fn run(&mut self) -> TaskOutcome {
+        let op = self.ops.pick(&mut self.rng);
+        let batch: Vec<String> = match op {
+            Op::MGET | Op::MSET => (0..self.config.mget_keys)
+                .map(|_| self.keys.next().to_owned())
+                .collect(),
+            _ => Vec::new(),
+        };
+        let batch: Vec<&str> = batch.iter().map(String::as_str).collect();
+        let key = self.keys.next();
+        let counter = counter_key(key);
+        let value = self.sizes.value(&self.config.data_bytes);
+
         let start = Instant::now();
-        let op = if r < self.config.ratio {
-            self.client
-                .set(
-                    self.config.key.as_str(),
-                    self.config.data_bytes.deref(),
-                    0,
-                    0,
+        let r = match op {
+            Op::SET => self.client.set(key, value).map(|_| Some(value.len())),
+            Op::GET => self.client.get(key).map(|v| v.map(|v| v.len())),
+            Op::DELETE => self.client.del(&[key]).map(|n| (n > 0).then_some(0)),
+            Op::INCR => self.client.incr(&counter).map(|_| Some(0)),
+            Op::DECR => self.client.decr(&counter).map(|_| Some(0)),
+            Op::MGET => self.client.mget(&batch).map(|values| {
+                Some(
+                    values
+                        .iter()
+                        .map(|v| v.as_ref().map_or(0, |v| v.len()))
+                        .sum(),
                 )
-                .unwrap();
-            "SET"
-        } else {
-            self.client.get(self.config.key.as_str()).unwrap();
-            "GET"
+            }),
+            Op::MSET => {
+                let items: Vec<_> = batch.iter().map(|k| (*k, value)).collect();
+                self.client
+                    .mset(&items)
+                    .map(|_| Some(value.len() * items.len()))
+            }
+            op => unreachable!("{} is rejected for REDIS at startup", op.name()),
         };
-        TaskResult(op.into(), start.elapsed())
+        let r = outcome(op.name(), start, r);
+        if broken(&r) {
+            if let Ok(client) = Self::connect(&self.config) {
+                self.client = client;
+            }
+        }
+        r
+    }
+}
+
+/// Fails `f` with `TimedOut` once `timeout` elapses, for the async clients
+/// that have no socket timeouts of their own.
+async fn timed<T>(
+    timeout: Option<Duration>,
+    f: impl Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    match timeout {
+        Some(t) => tokio::time::timeout(t, f)
+            .await
+            .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into())),
+        None => f.await,
+    }
+}
+
+struct Tokio {
+    config: Arc<Config>,
+    client: TokioClient,
+    keys: KeyGen,
+    sizes: SizeGen,
+    rng: SmallRng,
+}
+
+impl Tokio {
+    async fn new(c: Arc<Config>) -> io::Result<Self> {
+        Ok(Tokio {
+            client: TokioClient::connect(&addr(&c)).await?,
+            keys: KeyGen::new(&c).expect("key distribution checked at startup"),
+            sizes: SizeGen::new(&c.data).expect("data distribution checked at startup"),
+            config: c,
+            rng: SmallRng::from_entropy(),
+        })
+    }
+}
+
+impl AsyncTask for Tokio {
+    fn init(&mut self) -> LocalBoxFuture<'_, ()> {
+        Box::pin(async move {
+            if !self.config.data_bytes.is_empty() {
+                for key in self.keys.all() {
+                    let value = self.sizes.value(&self.config.data_bytes);
+                    self.client.set(&key, value, 0, 0).await.unwrap();
+                    let v = self.client.get(&key).await.unwrap().unwrap();
+                    assert!(v == value)
+                }
+            }
+        })
+    }
+    fn run(&mut self) -> LocalBoxFuture<'_, TaskOutcome> {
+        Box::pin(async move {
+            let r: f64 = self.rng.gen();
+            let key = self.keys.next();
+            let value = self.sizes.value(&self.config.data_bytes);
+            let timeout = self.config.timeout;
+            let start = Instant::now();
+            let (op, r) = if r < self.config.ratio {
+                let set = self.client.set(key, value, 0, 0);
+                ("SET", timed(timeout, set).await.map(|_| Some(value.len())))
+            } else {
+                let get = self.client.get(key);
+                ("GET", timed(timeout, get).await.map(|v| v.map(|v| v.len())))
+            };
+            let r = outcome(op, start, r);
+            if broken(&r) {
+                if let Ok(client) = TokioClient::connect(&addr(&self.config)).await {
+                    self.client = client;
+                }
+            }
+            r
+        })
+    }
+}
+
+#[cfg(feature = "uring")]
+struct Uring {
+    config: Arc<Config>,
+    client: UringClient,
+    keys: KeyGen,
+    sizes: SizeGen,
+    rng: SmallRng,
+}
+
+#[cfg(feature = "uring")]
+impl Uring {
+    async fn new(c: Arc<Config>) -> io::Result<Self> {
+        Ok(Uring {
+            client: UringClient::connect(&addr(&c)).await?,
+            keys: KeyGen::new(&c).expect("key distribution checked at startup"),
+            sizes: SizeGen::new(&c.data).expect("data distribution checked at startup"),
+            config: c,
+            rng: SmallRng::from_entropy(),
+        })
+    }
+}
+
+#[cfg(feature = "uring")]
+impl AsyncTask for Uring {
+    fn init(&mut self) -> LocalBoxFuture<'_, ()> {
+        Box::pin(async move {
+            if !self.config.data_bytes.is_empty() {
+                for key in self.keys.all() {
+                    let value = self.sizes.value(&self.config.data_bytes);
+                    self.client.set(&key, value, 0, 0).await.unwrap();
+                    let v = self.client.get(&key).await.unwrap().unwrap();
+                    assert!(v == value)
+                }
+            }
+        })
+    }
+    fn run(&mut self) -> LocalBoxFuture<'_, TaskOutcome> {
+        Box::pin(async move {
+            let r: f64 = self.rng.gen();
+            let key = self.keys.next();
+            let value = self.sizes.value(&self.config.data_bytes);
+            let timeout = self.config.timeout;
+            let start = Instant::now();
+            let (op, r) = if r < self.config.ratio {
+                let set = self.client.set(key, value, 0, 0);
+                ("SET", timed(timeout, set).await.map(|_| Some(value.len())))
+            } else {
+                let get = self.client.get(key);
+                ("GET", timed(timeout, get).await.map(|v| v.map(|v| v.len())))
+            };
+            let r = outcome(op, start, r);
+            if broken(&r) {
+                if let Ok(client) = UringClient::connect(&addr(&self.config)).await {
+                    self.client = client;
+                }
+            }
+            r
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    /// The config of a run against a fresh mock server.
+    fn config(args: &[&str]) -> Arc<Config> {
+        let addr = mock::Server::new().listen_tcp("127.0.0.1:0").unwrap();
+        let port = addr.port().to_string();
+        let mut argv = vec!["bench", "-p", &port, "-d", "100", "-K", "20"];
+        argv.extend(args);
+        crate::prepare(Config::try_parse_from(argv).unwrap()).unwrap()
+    }
+
+    /// Populates the keyspace and runs `requests`, returning the requests that
+    /// missed. Any other failure panics.
+    fn run(args: &[&str], requests: usize) -> usize {
+        let mut task = task_factory(config(args)).unwrap();
+        task.init();
+        let mut misses = 0;
+        for _ in 0..requests {
+            for outcome in task.run_batch() {
+                match outcome {
+                    Ok(_) => (),
+                    Err(TaskError(_, ErrorClass::Miss)) => misses += 1,
+                    Err(e) => panic!("{args:?}: {e:?}"),
+                }
+            }
+        }
+        misses
+    }
+
+    #[test]
+    fn memrs() {
+        assert_eq!(run(&["-t", "memrs"], 200), 0);
+        assert_eq!(run(&["-t", "memrs", "-c", "30"], 200), 0);
+    }
+
+    #[test]
+    fn rsmem() {
+        assert_eq!(run(&["-t", "rsmem"], 200), 0);
+    }
+
+    #[test]
+    fn basic() {
+        assert_eq!(run(&["-t", "basic"], 200), 0);
+        assert_eq!(run(&["-t", "basic", "--pipeline", "8"], 50), 0);
+        let ops = "set,get,add,replace,append,prepend,cas,gets,delete,incr,decr,touch,gat,mget";
+        run(&["-t", "basic", "--ops", ops, "--pipeline", "4"], 200);
+    }
+
+    #[test]
+    fn meta() {
+        assert_eq!(run(&["-t", "meta"], 200), 0);
+        let ops = "set,get,add,replace,append,prepend,cas,gets,delete,incr,decr,touch,gat,mget";
+        run(&["-t", "meta", "--ops", ops], 200);
+    }
+
+    #[test]
+    fn tokio() {
+        let c = config(&["-t", "tokio", "-e", "tokio"]);
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let mut task = async_task_factory(c).await.unwrap();
+            task.init().await;
+            for _ in 0..200 {
+                task.run().await.unwrap();
+            }
+        });
     }
 }
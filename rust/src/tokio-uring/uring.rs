@@ -1,70 +1,135 @@
-// use tokio_uring::net::UnixStream;
-// use tokio::io::{Error, ErrorKind};
+use std::io::{self, ErrorKind, Write};
+use std::mem;
+use std::net::ToSocketAddrs;
+use tokio_uring::buf::{IoBuf, IoBufMut};
+use tokio_uring::net::{TcpStream, UnixStream};
+use tokio_uring::BufResult;
 
-// pub struct UringClient {
-//     stream: UnixStream,
-// }
+use crate::client;
 
-// impl UringClient {
-//     pub async fn connect(addr: &str) -> Result<Self> {
-//         Ok(Self { stream: UnixStream::connect(addr).await? })
-//     }
+enum UringStream {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
 
-//     pub async fn set(
-//         &mut self,
-//         key: &str,
-//         value: &[u8],
-//         flags: u32,
-//         expiration: u32,
-//     ) -> Result<bool, Error> {
-//         let mut cmd: Vec<u8> = format!("set {} {} {} {}\r\n", key, flags, expiration, value.len()).into_bytes();
-//         cmd.extend_from_slice(value);
-//         cmd.extend_from_slice(b"\r\n");
-//         let (res, buf) = self.stream.write(cmd).await;
-//         res.unwrap();
+impl UringStream {
+    async fn read<T: IoBufMut>(&self, buf: T) -> BufResult<usize, T> {
+        match self {
+            UringStream::Unix(s) => s.read(buf).await,
+            UringStream::Tcp(s) => s.read(buf).await,
+        }
+    }
 
-//         let (res, cmd) = self.stream.read(buf).await;
-//         let response = std::str::from_utf8(&cmd[..res.unwrap()]).unwrap();
-//         if response == "STORED\r\n" {
-//             Ok(true)
-//         } else {
-//             Err(Error::new(
-//                 ErrorKind::Other,
-//                 format!("memcached error: {}", response),
-//             ))
-//         }
-//     }
+    async fn write_all<T: IoBuf>(&self, buf: T) -> BufResult<(), T> {
+        match self {
+            UringStream::Unix(s) => s.write_all(buf).await,
+            UringStream::Tcp(s) => s.write_all(buf).await,
+        }
+    }
+}
 
-//     pub async fn get(&mut self, key: &str) -> Result<Option<Vec<u8>>> {
-//         let cmd: Vec<u8> = format!("get {key}\r\n").into_bytes();
-//         let (res, _buf) = self.stream.write(cmd).await;
-//         res.unwrap();
+/// Text protocol client that owns its write and read buffers, handing them to
+/// the ring for every submission and taking them back on completion, so requests
+/// do not allocate. Replies are parsed with the same reader as `client::Client`.
+pub struct UringClient {
+    stream: UringStream,
+    wbuf: Vec<u8>,
+    rbuf: client::ReadBuf,
+}
 
-//         let mut response = Vec::new();
-//         let mut buf = vec![0u8; 1024];
-//         loop {
-//             let (result, nbuf) = self.stream.read(buf).await;
-//             buf = nbuf;
-//             let read = result.unwrap();
-//             if read == 0 {
-//                 break;
-//             }
+impl UringClient {
+    pub async fn connect(addr: &str) -> io::Result<Self> {
+        let stream = if addr.contains(".sock") {
+            UringStream::Unix(UnixStream::connect(addr).await?)
+        } else {
+            let addr = addr
+                .to_socket_addrs()?
+                .next()
+                .ok_or(ErrorKind::AddrNotAvailable)?;
+            UringStream::Tcp(TcpStream::connect(addr).await?)
+        };
+        Ok(Self {
+            stream,
+            wbuf: Vec::with_capacity(4096),
+            rbuf: client::ReadBuf::default(),
+        })
+    }
 
-//             response.extend_from_slice(&buf[..read]);
-//             if response.ends_with(b"\r\nEND\r\n") {
-//                 break;
-//             }
-//         }
+    pub async fn set(
+        &mut self,
+        key: &str,
+        value: &[u8],
+        flags: u32,
+        expiration: u32,
+    ) -> io::Result<bool> {
+        // command line, value and terminator go out in a single submission
+        let mut cmd = mem::take(&mut self.wbuf);
+        cmd.clear();
+        write!(
+            cmd,
+            "set {} {} {} {}\r\n",
+            key,
+            flags,
+            expiration,
+            value.len()
+        )?;
+        cmd.extend_from_slice(value);
+        cmd.extend_from_slice(b"\r\n");
+        self.write(cmd).await?;
 
-//         let response_lines: Vec<&[u8]> = response.split(|b| *b == b'\r' || *b == b'\n').collect();
-//         if response_lines.len() < 3 {
-//             return Err(ErrorKind::InvalidData.into());
-//         }
+        loop {
+            if let Some(line) = self.rbuf.line()? {
+                return match line {
+                    "STORED" => Ok(true),
+                    r => Err(client::unexpected(r)),
+                };
+            }
+            self.fill().await?;
+        }
+    }
 
-//         let header = std::str::from_utf8(response_lines[0]).unwrap();
-//         if header == "END" {
-//             return Err(ErrorKind::InvalidData.into());
-//         }
-//         Ok(Some(response_lines[2].to_vec()))
-//     }
-// }
+    pub async fn get(&mut self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        let mut cmd = mem::take(&mut self.wbuf);
+        cmd.clear();
+        write!(cmd, "get {key}\r\n")?;
+        self.write(cmd).await?;
+
+        let mut value = None;
+        loop {
+            let len = loop {
+                if let Some(header) = self.rbuf.line()? {
+                    if header == "END" {
+                        return Ok(value);
+                    }
+                    // the value may contain CR/LF, so take its length from the VALUE header
+                    break match header.split(' ').collect::<Vec<_>>()[..] {
+                        ["VALUE", _, _, len] => len.parse().ok(),
+                        _ => None,
+                    }
+                    .ok_or_else(|| client::unexpected(header))?;
+                }
+                self.fill().await?;
+            };
+            value = loop {
+                if let Some(v) = self.rbuf.value(len)? {
+                    break Some(v);
+                }
+                self.fill().await?;
+            };
+        }
+    }
+
+    async fn write(&mut self, cmd: Vec<u8>) -> io::Result<()> {
+        let (res, cmd) = self.stream.write_all(cmd).await;
+        self.wbuf = cmd;
+        res
+    }
+
+    /// Reads once into the read buffer.
+    async fn fill(&mut self) -> io::Result<()> {
+        let buf = self.rbuf.lend();
+        let len = buf.len();
+        let (res, slice) = self.stream.read(buf.slice(len..)).await;
+        self.rbuf.give_back(slice.into_inner(), res)
+    }
+}